@@ -0,0 +1,230 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Reproducible package archives.
+//!
+//! Building a package twice from the same inputs must produce the same archive. To do so,
+//! entries are written in a sorted order, with normalized owners, and with modification times
+//! clamped to `SOURCE_DATE_EPOCH` if set. The gzip header does not contain any timestamp.
+//!
+//! Archives may be compressed with gzip, xz or Zstandard.
+//!
+//! Along with the package's metadata and files, archives contain a file manifest (see
+//! [`common::file_manifest`]).
+
+use crate::desc::Config;
+use common::{
+	anyhow::{anyhow, Result},
+	file_manifest::{self, FileManifest, FileType},
+	flate2::{Compression, GzBuilder},
+	package::ArchiveFormat,
+	tar::{self, EntryType, Header, HeaderMode},
+	util::{read_package_archive, to_hex},
+	xz2::write::XzEncoder,
+	zstd::stream::write::Encoder as ZstdEncoder,
+};
+use sha2::{Digest, Sha256};
+use std::{
+	collections::BTreeMap,
+	env,
+	fs::{self, File},
+	io::{self, Read, Write},
+	os::unix::fs::MetadataExt,
+	path::Path,
+};
+
+/// Returns the timestamp to which modification times in archives are clamped, from the
+/// `SOURCE_DATE_EPOCH` environment variable.
+///
+/// If the variable is not set, the function returns `None`, and modification times are kept
+/// as is.
+pub fn source_date_epoch() -> Result<Option<u64>> {
+	match env::var("SOURCE_DATE_EPOCH") {
+		Ok(val) => val
+			.trim()
+			.parse()
+			.map(Some)
+			.map_err(|_| anyhow!("invalid `SOURCE_DATE_EPOCH`: {val}")),
+		Err(env::VarError::NotPresent) => Ok(None),
+		Err(e) => Err(anyhow!("invalid `SOURCE_DATE_EPOCH`: {e}")),
+	}
+}
+
+/// Creates a header for the file with the metadata `meta`, normalized for reproducibility.
+fn normalized_header(meta: &fs::Metadata, epoch: Option<u64>) -> io::Result<Header> {
+	let mut header = Header::new_gnu();
+	header.set_metadata_in_mode(meta, HeaderMode::Complete);
+	let mtime = meta.mtime().max(0) as u64;
+	header.set_mtime(epoch.map_or(mtime, |epoch| mtime.min(epoch)));
+	header.set_uid(0);
+	header.set_gid(0);
+	header.set_username("root")?;
+	header.set_groupname("root")?;
+	Ok(header)
+}
+
+/// Appends the file at `path` to `tar` with the name `name`, then its children in a sorted order
+/// if it is a directory.
+fn append_tree<W: Write>(
+	tar: &mut tar::Builder<W>,
+	path: &Path,
+	name: &Path,
+	epoch: Option<u64>,
+) -> io::Result<()> {
+	let meta = fs::symlink_metadata(path)?;
+	let mut header = normalized_header(&meta, epoch)?;
+	let file_type = meta.file_type();
+	if file_type.is_symlink() {
+		tar.append_link(&mut header, name, fs::read_link(path)?)?;
+	} else if file_type.is_file() {
+		tar.append_data(&mut header, name, File::open(path)?)?;
+	} else {
+		tar.append_data(&mut header, name, io::empty())?;
+	}
+	if file_type.is_dir() {
+		let mut entries = fs::read_dir(path)?
+			.map(|e| e.map(|e| e.file_name()))
+			.collect::<io::Result<Vec<_>>>()?;
+		entries.sort_unstable();
+		for entry in entries {
+			append_tree(tar, &path.join(&entry), &name.join(&entry), epoch)?;
+		}
+	}
+	Ok(())
+}
+
+/// Returns the file manifest of the content of the directory `data`.
+///
+/// Owners are normalized the same way as in the archive. Regular files matching `config` are
+/// marked as configuration files.
+fn file_manifest(data: &Path, config: Option<&Config>) -> io::Result<String> {
+	let mut manifest = FileManifest::from_dir(data)?;
+	for file in &mut manifest.file {
+		file.uid = 0;
+		file.gid = 0;
+		file.config = file.file_type == FileType::Regular
+			&& config.is_some_and(|config| config.contains(&file.path));
+	}
+	toml::to_string(&manifest).map_err(io::Error::other)
+}
+
+/// Writes the tar archive of a package to `out`, then returns `out`.
+fn write_tar<W: Write>(
+	out: W,
+	metadata: &Path,
+	data: &Path,
+	config: Option<&Config>,
+	epoch: Option<u64>,
+) -> io::Result<W> {
+	let mut tar = tar::Builder::new(out);
+	let meta = fs::metadata(metadata)?;
+	let mut header = normalized_header(&meta, epoch)?;
+	tar.append_data(&mut header, "metadata.toml", File::open(metadata)?)?;
+	// The manifest is written with the same header as the metadata, except for its size
+	let manifest = file_manifest(data, config)?;
+	header.set_size(manifest.len() as u64);
+	tar.append_data(
+		&mut header,
+		file_manifest::ARCHIVE_PATH,
+		manifest.as_bytes(),
+	)?;
+	append_tree(&mut tar, data, Path::new("data"), epoch)?;
+	// TODO add install/update/remove hooks
+	tar.into_inner()
+}
+
+/// Writes a package archive at `output`, made of the build descriptor at `metadata` and the
+/// content of the directory `data`.
+///
+/// Arguments:
+/// - `format` is the compression format of the archive
+/// - `config` describes the configuration files of the package, if any
+/// - `epoch` is the timestamp to which modification times are clamped, if any
+pub fn write(
+	output: &Path,
+	format: ArchiveFormat,
+	metadata: &Path,
+	data: &Path,
+	config: Option<&Config>,
+	epoch: Option<u64>,
+) -> io::Result<()> {
+	let file = File::create(output)?;
+	match format {
+		ArchiveFormat::Gzip => {
+			// Use fixed values for the fields of the gzip header that depend on the environment
+			let enc = GzBuilder::new()
+				.mtime(0)
+				.operating_system(255)
+				.write(file, Compression::default());
+			write_tar(enc, metadata, data, config, epoch)?.finish()?;
+		}
+		ArchiveFormat::Xz => {
+			let enc = XzEncoder::new(file, 6);
+			write_tar(enc, metadata, data, config, epoch)?.finish()?;
+		}
+		ArchiveFormat::Zstd => {
+			let enc = ZstdEncoder::new(file, 0)?;
+			write_tar(enc, metadata, data, config, epoch)?.finish()?;
+		}
+	}
+	Ok(())
+}
+
+/// An entry of an archive, as compared between two archives.
+#[derive(Debug, Eq, PartialEq)]
+pub struct EntrySummary {
+	/// The type of the entry.
+	pub entry_type: EntryType,
+	/// The permissions of the entry.
+	pub mode: u32,
+	/// The owner of the entry.
+	pub uid: u64,
+	/// The group of the entry.
+	pub gid: u64,
+	/// The modification time of the entry.
+	pub mtime: u64,
+	/// The target of the entry, if it is a link.
+	pub link: Option<String>,
+	/// The SHA-256 hash of the content of the entry.
+	pub sha256: String,
+}
+
+/// Lists the entries of the package archive at `path`, by path.
+pub fn list_entries(path: &Path) -> Result<BTreeMap<String, EntrySummary>> {
+	let mut archive = read_package_archive(path)?;
+	let mut entries = BTreeMap::new();
+	for entry in archive.entries()? {
+		let mut entry = entry?;
+		let path = entry.path()?.to_string_lossy().into_owned();
+		let header = entry.header();
+		let mut summary = EntrySummary {
+			entry_type: header.entry_type(),
+			mode: header.mode()? & 0o7777,
+			uid: header.uid()?,
+			gid: header.gid()?,
+			mtime: header.mtime()?,
+			link: entry.link_name()?.map(|l| l.to_string_lossy().into_owned()),
+			sha256: String::new(),
+		};
+		let mut content = vec![];
+		entry.read_to_end(&mut content)?;
+		summary.sha256 = to_hex(&Sha256::digest(&content));
+		entries.insert(path, summary);
+	}
+	Ok(entries)
+}
@@ -0,0 +1,283 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Bootstrapping of a cross-compilation toolchain.
+//!
+//! Bootstrapping builds, in a sysroot, the toolchain required to cross-compile packages for
+//! another target triplet. It is made of stages, each building one package from the bootstrap
+//! descriptors directory.
+//!
+//! Completed stages are recorded in the sysroot, so that bootstrapping resumes at the stage that
+//! failed when run again.
+
+use crate::{get_arch, BootstrapArgs, BuildArgs};
+use common::{
+	anyhow::{anyhow, bail, Result},
+	package::ArchiveFormat,
+};
+use serde::{Deserialize, Serialize};
+use std::{env, fs, io, os::unix::fs::symlink, path::Path};
+
+/// The file in the sysroot recording the progress of bootstrapping.
+const STATE_FILE: &str = "tools/bootstrap.toml";
+
+/// A machine for which a stage is built.
+#[derive(Clone, Copy)]
+enum Machine {
+	/// The machine on which bootstrapping runs.
+	Build,
+	/// The machine for which the toolchain is bootstrapped.
+	Host,
+}
+
+/// A bootstrap stage.
+struct Stage {
+	/// The name of the stage, which is also the name of the directory containing the descriptor
+	/// of the package it builds.
+	name: &'static str,
+	/// The machine on which the package runs. If `None`, the builder's default is used.
+	host: Option<Machine>,
+	/// The machine for which the package builds. If `None`, the builder's default is used.
+	target: Option<Machine>,
+}
+
+/// The bootstrap stages, in order.
+const STAGES: &[Stage] = &[
+	Stage {
+		name: "binutils",
+		host: Some(Machine::Build),
+		target: Some(Machine::Host),
+	},
+	Stage {
+		name: "gcc",
+		host: Some(Machine::Build),
+		target: Some(Machine::Host),
+	},
+	Stage {
+		name: "linux-headers",
+		host: None,
+		target: None,
+	},
+	Stage {
+		name: "musl",
+		host: Some(Machine::Host),
+		target: None,
+	},
+	Stage {
+		name: "gcc-libgcc",
+		host: Some(Machine::Build),
+		target: Some(Machine::Host),
+	},
+	Stage {
+		name: "zlib",
+		host: Some(Machine::Host),
+		target: None,
+	},
+	Stage {
+		name: "libstdc++",
+		host: Some(Machine::Host),
+		target: None,
+	},
+];
+
+/// The progress of bootstrapping, stored in the sysroot.
+#[derive(Default, Deserialize, Serialize)]
+struct State {
+	/// The triplet of the machine for which the toolchain is bootstrapped.
+	host: String,
+	/// The names of the completed stages.
+	#[serde(default)]
+	done: Vec<String>,
+}
+
+impl State {
+	/// Reads the state from the sysroot at `sysroot`. If bootstrapping has not started yet, the
+	/// function returns `None`.
+	fn read(sysroot: &Path) -> Result<Option<Self>> {
+		let path = sysroot.join(STATE_FILE);
+		match fs::read_to_string(&path) {
+			Ok(content) => toml::from_str(&content)
+				.map(Some)
+				.map_err(|e| anyhow!("invalid bootstrap state `{}`: {e}", path.display())),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	/// Tells whether the stage with the given `name` has been completed.
+	fn is_done(&self, name: &str) -> bool {
+		self.done.iter().any(|s| s == name)
+	}
+
+	/// Writes the state to the sysroot at `sysroot`.
+	fn write(&self, sysroot: &Path) -> Result<()> {
+		fs::write(sysroot.join(STATE_FILE), toml::to_string(self)?)?;
+		Ok(())
+	}
+}
+
+/// Creates a symbolic link at `path` pointing to `target`, replacing any previous link.
+fn force_symlink(target: &str, path: &Path) -> Result<()> {
+	match fs::symlink_metadata(path) {
+		Ok(m) if m.file_type().is_symlink() => fs::remove_file(path)?,
+		Ok(_) => bail!("`{}` exists and is not a symbolic link", path.display()),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+		Err(e) => return Err(e.into()),
+	}
+	symlink(target, path)?;
+	Ok(())
+}
+
+/// Creates the base directories and symbolic links of the sysroot, for the given `host`.
+fn create_layout(sysroot: &Path, host: &str) -> Result<()> {
+	for dir in [
+		"tools",
+		"etc",
+		"var",
+		"usr/bin",
+		"usr/lib",
+		"usr/lib32",
+		"usr/sbin",
+	] {
+		fs::create_dir_all(sysroot.join(dir))?;
+	}
+	for dir in ["bin", "lib", "sbin"] {
+		force_symlink(&format!("usr/{dir}"), &sysroot.join(dir))?;
+	}
+	if get_arch(host) == "x86_64" {
+		force_symlink("usr/lib", &sysroot.join("lib64"))?;
+		force_symlink("lib", &sysroot.join("usr/lib64"))?;
+	}
+	Ok(())
+}
+
+/// Bootstraps a toolchain.
+///
+/// `build` is the triplet of the machine on which bootstrapping runs.
+pub fn bootstrap(args: BootstrapArgs, build: String) -> Result<()> {
+	let mut state = State::read(&args.sysroot)?.unwrap_or_else(|| State {
+		host: args.host.clone(),
+		done: vec![],
+	});
+	if state.host != args.host {
+		bail!(
+			"`{}` is being bootstrapped for `{}`, not `{}`",
+			args.sysroot.display(),
+			state.host,
+			args.host
+		);
+	}
+	println!("[INFO] Create base layout...");
+	create_layout(&args.sysroot, &args.host)?;
+	state.write(&args.sysroot)?;
+	let sysroot = args.sysroot.canonicalize()?;
+	// Make the tools built by previous stages available to the next ones
+	let tools = sysroot.join("tools/bin");
+	let path = env::var_os("PATH").unwrap_or_default();
+	let path = env::join_paths([tools].into_iter().chain(env::split_paths(&path)))?;
+	env::set_var("PATH", path);
+	// Triplets are passed explicitly to each stage
+	for var in ["BUILD", "HOST", "TARGET"] {
+		env::remove_var(var);
+	}
+	let triplet = |machine: Option<Machine>| {
+		machine.map(|m| match m {
+			Machine::Build => build.clone(),
+			Machine::Host => args.host.clone(),
+		})
+	};
+	for stage in STAGES {
+		if state.is_done(stage.name) {
+			println!("[INFO] Stage `{}` is already done", stage.name);
+			continue;
+		}
+		println!("[INFO] Stage `{}`...", stage.name);
+		let res = crate::build(BuildArgs {
+			from: args.from.join(stage.name),
+			to: sysroot.clone(),
+			package: false,
+			jobs: args.jobs,
+			build: Some(build.clone()),
+			host: triplet(stage.host),
+			target: triplet(stage.target),
+			chroot: false,
+			sandbox: false,
+			force: false,
+			archive_format: ArchiveFormat::Gzip,
+			features: vec![],
+			no_default_features: false,
+			check: false,
+			ignore_check: false,
+			debug: args.debug,
+			work_dir: args.work_dir.clone(),
+		});
+		if let Err(e) = res {
+			bail!(
+				"stage `{}` failed: {e}\nRun the same command again to resume from this stage",
+				stage.name
+			);
+		}
+		state.done.push(stage.name.to_owned());
+		state.write(&sysroot)?;
+	}
+	println!("[INFO] Toolchain is ready in `{}`", sysroot.display());
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::util::create_tmp_dir;
+
+	/// Returns the position of the stage `name` in the bootstrap order.
+	fn position(name: &str) -> usize {
+		STAGES.iter().position(|s| s.name == name).unwrap()
+	}
+
+	#[test]
+	fn stage_order() {
+		// Each stage is built after the stages it requires
+		for (before, after) in [
+			("binutils", "gcc"),
+			("gcc", "musl"),
+			("linux-headers", "musl"),
+			("musl", "gcc-libgcc"),
+			("gcc-libgcc", "zlib"),
+			("gcc-libgcc", "libstdc++"),
+		] {
+			assert!(position(before) < position(after), "{before} after {after}");
+		}
+	}
+
+	#[test]
+	fn resume() {
+		let sysroot = create_tmp_dir(env::temp_dir()).unwrap();
+		create_layout(&sysroot, "x86_64-unknown-linux-musl").unwrap();
+		let state = State {
+			host: "x86_64-unknown-linux-musl".to_owned(),
+			done: vec!["binutils".to_owned(), "gcc".to_owned()],
+		};
+		state.write(&sysroot).unwrap();
+		let state = State::read(&sysroot).unwrap().unwrap();
+		fs::remove_dir_all(sysroot).unwrap();
+
+		// Bootstrapping resumes at the first stage that has not been completed
+		let next = STAGES.iter().find(|s| !state.is_done(s.name)).unwrap();
+		assert_eq!(next.name, "linux-headers");
+	}
+}
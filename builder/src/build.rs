@@ -18,35 +18,48 @@
 
 //! Implementation of the package building procedure.
 
-use crate::desc::BuildDescriptor;
+use crate::{
+	archive,
+	desc::{BuildDescriptor, Source},
+	log::BuildLog,
+	sandbox::{self, Sandbox},
+};
 use common::{
 	anyhow::{anyhow, bail, Result},
-	flate2::{write::GzEncoder, Compression},
 	maestro_utils::{fhs, user::get_euid},
-	package::{DependencyType, Package},
+	package::{AppliedPatch, ArchiveFormat, DependencyType, Package},
 	repository::{
 		get_package_with_constraint, get_recursive_dependencies, remote::download_packages,
-		PackagesWithRepositoryMap, Repository,
+		PackagesWithRepositoryMap, PackagesWithRepositoryVec, Repository,
 	},
-	tar, tokio,
-	util::{create_tmp_dir, current_arch},
+	tokio,
+	util::{concat_paths, create_tmp_dir, create_tmp_file, current_arch, sha256_file, to_hex},
 	Environment,
 };
+use sha2::{Digest, Sha256};
 use std::{
-	ffi::CString,
-	fs::{self, File},
-	io,
+	collections::BTreeMap,
+	env,
+	ffi::{CString, OsString},
+	fs,
+	io::{self, Read, Write},
 	os::unix::{ffi::OsStrExt, fs::chroot, process::CommandExt},
 	path::{Path, PathBuf},
 	process::Command,
-	str,
+	ptr, str,
 	sync::Arc,
+	time::{Duration, Instant},
 };
 
-/// Get original build-hook file path
-fn get_build_hook_path(input_path: &Path) -> io::Result<PathBuf> {
+/// The name of the hook building the package.
+const BUILD_HOOK: &str = "build-hook";
+/// The name of the hook running the package's test suite.
+const CHECK_HOOK: &str = "check-hook";
+
+/// Get original hook file path
+fn get_hook_path(input_path: &Path, name: &str) -> io::Result<PathBuf> {
 	let absolute_input = fs::canonicalize(input_path)?;
-	Ok(absolute_input.join("build-hook"))
+	Ok(absolute_input.join(name))
 }
 
 /// Populates `sysroot/dev/` with the basic character device nodes that build hooks expect. Without
@@ -80,12 +93,25 @@ fn create_dev_nodes(sysroot: &Path) -> io::Result<()> {
 	Ok(())
 }
 
+/// The isolation of build hooks from the host.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Isolation {
+	/// Hooks run directly on the host.
+	None,
+	/// Hooks run in a chroot of the sysroot. This requires root privileges.
+	Chroot,
+	/// Hooks run in an unprivileged sandbox, see [`crate::sandbox`].
+	Sandbox,
+}
+
 /// A build process is the operation of converting source code into an installable package.
 ///
 /// To build a package, the following files are required:
 /// - `build.toml`: Information to prepare for building the package
 /// - `build-hook`: The script to build the package
 ///
+/// Optionally, a `check-hook` script runs the package's test suite after it has been built.
+///
 /// The package is build and then installed to a fake system root, which is then compressed.
 pub struct BuildProcess {
 	/// The path to the directory containing information to build the package.
@@ -99,85 +125,343 @@ pub struct BuildProcess {
 	pub(crate) install_path: PathBuf,
 	/// The path to the system root.
 	pub(crate) sysroot: PathBuf,
-	/// Building in chroot environment?
-	chroot: bool,
+	/// The isolation of hooks from the host.
+	isolation: Isolation,
+	/// The list of enabled build features.
+	features: Vec<String>,
+	/// Tells whether checks are enabled.
+	check: bool,
+	/// The log of the build.
+	log: BuildLog,
+	/// The dependencies to install in the sysroot. If `None`, the sysroot already exists.
+	build_deps: Option<BuildDeps>,
 }
 
-/// Creates a sysroot for building the package, with its dependencies installed.
+/// The inputs of a build, read before anything is written to the work directory, so that it
+/// can be told whether the build is needed.
+pub struct BuildInputs {
+	/// The path to the directory containing information to build the package.
+	input_path: PathBuf,
+	/// The build descriptor.
+	build_desc: BuildDescriptor,
+	/// The list of enabled build features.
+	features: Vec<String>,
+	/// Tells whether checks are enabled.
+	check: bool,
+	/// The dependencies to install in the sysroot. If `None`, the sysroot already exists.
+	build_deps: Option<BuildDeps>,
+}
+
+/// The dependencies installed in the sysroot to build a package.
+struct BuildDeps {
+	/// The host's repositories.
+	repos: Vec<Repository>,
+	/// The packages to install, sorted by name, with the index of the repository they come from.
+	packages: Vec<(Package, usize)>,
+}
+
+/// The maximum duration to wait for the host environment to be free.
+const HOST_ENV_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Acquires the host environment at `sysroot`.
 ///
-/// Arguments:
-/// - `sysroot` the path to the system root
-/// - `input_path` is the path to the directory containing information to build the package
-/// - `package` is the package to build
-async fn create_sysroot(sysroot: &Path, input_path: &Path, package: &Package) -> Result<()> {
-	if let Err(e) = fhs::create_dirs(sysroot, false) {
-		bail!("FHS creation failed: {e}");
+/// Several packages may be built at the same time: the function waits for the host environment
+/// to be free, failing after [`HOST_ENV_TIMEOUT`].
+async fn acquire_host_env(sysroot: &Path, arch: &str) -> Result<Environment> {
+	let start = Instant::now();
+	let mut waiting = false;
+	loop {
+		if let Some(env) = Environment::acquire(sysroot, arch)? {
+			break Ok(env);
+		}
+		let lock_path = Environment::lock_path(sysroot);
+		if start.elapsed() >= HOST_ENV_TIMEOUT {
+			bail!(
+				"timed out waiting for `{}` to be removed. If no other instance is running, it may \
+				 have been left by a crashed build",
+				lock_path.display()
+			);
+		}
+		if !waiting {
+			println!(
+				"[INFO] Waiting for the host environment to be free (lock file: `{}`)...",
+				lock_path.display()
+			);
+			waiting = true;
+		}
+		tokio::time::sleep(Duration::from_millis(100)).await;
 	}
-	create_dev_nodes(sysroot)?;
-	fs::copy(
-		get_build_hook_path(input_path)?,
-		sysroot.join("bin/build-hook"),
-	)?;
+}
 
+/// Opens the host environment at `sysroot`, to read its repositories.
+///
+/// Sandboxed builds do not require root privileges: the host environment is then opened
+/// read-only, without locking it.
+async fn open_host_env(sysroot: &Path, arch: &str, isolation: Isolation) -> Result<Environment> {
+	if isolation == Isolation::Sandbox {
+		return Ok(Environment::open_read_only(sysroot, arch)?);
+	}
+	acquire_host_env(sysroot, arch).await
+}
+
+/// Resolves the dependencies to install in the sysroot to build `package`, from the host's
+/// repositories.
+///
+/// Arguments:
+/// - `check` tells whether checks are enabled. If so, check dependencies are included.
+/// - `isolation` is the isolation of hooks from the host. Sandboxed builds do not update the
+///   indexes of remote repositories, which requires write access to the host environment
+async fn resolve_build_deps(
+	package: &Package,
+	check: bool,
+	isolation: Isolation,
+) -> Result<BuildDeps> {
 	let arch = current_arch();
-	let host_env =
-		Environment::acquire(Path::new("/"), arch)?.expect("unexpected environment lock");
+	let host_env = open_host_env(Path::new("/"), arch, isolation).await?;
 	let repos = host_env.list_repositories()?;
-	for r in &repos {
-		if let Some(remote) = r.get_remote() {
-			remote.fetch_index(&host_env).await?;
+	if isolation != Isolation::Sandbox {
+		for r in &repos {
+			if let Some(remote) = r.get_remote() {
+				remote.fetch_index(&host_env).await?;
+			}
 		}
 	}
 	let pkgs: PackagesWithRepositoryMap = package
 		.deps
 		.iter()
+		.filter(|dep| match dep.dep_type {
+			DependencyType::Optional => false,
+			DependencyType::Check => check,
+			_ => true,
+		})
 		.map(|dep| {
 			get_package_with_constraint(&repos, arch, &dep.name, Some(&dep.version_constraint))?
 				.map(|p| (p.1, p.0))
 				.ok_or_else(|| anyhow!("dependency `{}` not found in repositories", dep.name))
 		})
 		.collect::<Result<_>>()?;
-	let deps = get_recursive_dependencies(&pkgs, &repos, DependencyType::Build, arch)?
-		.into_iter()
+	let mut packages: Vec<_> =
+		get_recursive_dependencies(&pkgs, &repos, DependencyType::Build, arch)?
+			.into_iter()
+			.map(|(pkg, repo)| {
+				let i = repos.iter().position(|r| ptr::eq(r, repo)).unwrap();
+				(pkg, i)
+			})
+			.collect();
+	packages.sort_unstable_by(|(p0, _), (p1, _)| p0.name.cmp(&p1.name));
+	Ok(BuildDeps {
+		repos,
+		packages,
+	})
+}
+
+/// Creates a sysroot for building the package, with its dependencies installed.
+///
+/// Arguments:
+/// - `sysroot` the path to the system root
+/// - `input_path` is the path to the directory containing information to build the package
+/// - `deps` is the list of dependencies to install
+/// - `isolation` is the isolation of hooks from the host
+/// - `check` tells whether checks are enabled. If so, the check hook is installed too
+async fn create_sysroot(
+	sysroot: &Path,
+	input_path: &Path,
+	deps: &BuildDeps,
+	isolation: Isolation,
+	check: bool,
+) -> Result<()> {
+	if let Err(e) = fhs::create_dirs(sysroot, false) {
+		bail!("FHS creation failed: {e}");
+	}
+	if isolation == Isolation::Sandbox {
+		sandbox::prepare(sysroot)?;
+	} else {
+		create_dev_nodes(sysroot)?;
+	}
+	fs::copy(
+		get_hook_path(input_path, BUILD_HOOK)?,
+		sysroot.join("bin").join(BUILD_HOOK),
+	)?;
+	let check_hook = get_hook_path(input_path, CHECK_HOOK)?;
+	if check && check_hook.exists() {
+		fs::copy(check_hook, sysroot.join("bin").join(CHECK_HOOK))?;
+	}
+
+	let arch = current_arch();
+	let packages: PackagesWithRepositoryVec = deps
+		.packages
+		.iter()
+		.map(|(pkg, i)| (pkg.clone(), &deps.repos[*i]))
 		.collect();
-	download_packages(&deps, arch).await?;
-	drop(host_env);
+	// The host environment is locked only to download packages to its cache
+	if packages
+		.iter()
+		.any(|(pkg, repo)| !repo.is_in_cache(arch, pkg))
+	{
+		let host_env = acquire_host_env(Path::new("/"), arch).await?;
+		download_packages(&packages, arch).await?;
+		drop(host_env);
+	}
 	let mut target_env =
 		Environment::acquire(sysroot, arch)?.expect("unexpected environment lock");
-	target_env.install_packages(&deps)?;
+	target_env.install_packages(&packages, &[])?;
 	Ok(())
 }
 
-impl BuildProcess {
-	/// Creates a new instance.
+impl BuildInputs {
+	/// Reads the inputs of the build.
 	///
 	/// Arguments:
 	/// - `input_path` is the path to the directory containing information to build the package.
+	/// - `sysroot_exists` tells whether the package is built in an existing system root. If not,
+	///   the dependencies to install in a new system root are resolved
+	/// - `features` is the list of build features to enable
+	/// - `default_features` tells whether the features enabled by default are enabled too
+	/// - `check` tells whether the package's test suite is to be run after building it
+	/// - `isolation` is the isolation of hooks from the host
+	pub async fn read(
+		input_path: PathBuf,
+		sysroot_exists: bool,
+		features: &[String],
+		default_features: bool,
+		check: bool,
+		isolation: Isolation,
+	) -> Result<Self> {
+		let build_desc_path = input_path.join("metadata.toml");
+		let build_desc = fs::read_to_string(build_desc_path)?;
+		let mut build_desc = toml::from_str::<BuildDescriptor>(&build_desc)?;
+		let features = build_desc.enable_features(features, default_features)?;
+		build_desc.package.validate()?;
+		if let Some(network) = &build_desc.network {
+			if network.reason.trim().is_empty() {
+				bail!("network access during the build requires a `reason`");
+			}
+		}
+		let build_deps = if !sysroot_exists {
+			Some(resolve_build_deps(&build_desc.package, check, isolation).await?)
+		} else {
+			None
+		};
+		Ok(Self {
+			input_path,
+			build_desc,
+			features,
+			check,
+			build_deps,
+		})
+	}
+
+	/// Computes the hash of the inputs of the build, which are the build descriptor, the enabled
+	/// features, the hooks, the patches, the dependencies installed in the sysroot and the
+	/// `build`, `host` and `target` triplets.
+	///
+	/// Building a package twice with the same inputs is expected to produce the same package.
+	///
+	/// The content of sources is known from the descriptor only if they are pinned (see
+	/// [`Source::is_pinned`]). If any source is not, the function returns `None`.
+	pub fn input_hash(&self, build: &str, host: &str, target: &str) -> Result<Option<String>> {
+		if !self.build_desc.source.iter().all(Source::is_pinned) {
+			return Ok(None);
+		}
+		let mut hasher = Sha256::new();
+		// Each input is prefixed by its name and length, so that inputs cannot be confused
+		let mut input = |name: &str, value: &[u8]| {
+			hasher.update(name.as_bytes());
+			hasher.update((value.len() as u64).to_le_bytes());
+			hasher.update(value);
+		};
+		input("descriptor", toml::to_string(&self.build_desc)?.as_bytes());
+		input("features", self.features.join(" ").as_bytes());
+		let hooks = [(BUILD_HOOK, true), (CHECK_HOOK, self.check)];
+		for (hook, enabled) in hooks {
+			if !enabled {
+				continue;
+			}
+			match fs::read(get_hook_path(&self.input_path, hook)?) {
+				Ok(content) => input(hook, &content),
+				Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+				Err(e) => return Err(e.into()),
+			}
+		}
+		for patch in &self.build_desc.patch {
+			input("patch", &fs::read(self.input_path.join(&patch.path))?);
+		}
+		for (pkg, _) in self.build_deps.iter().flat_map(|d| &d.packages) {
+			input("dep", format!("{} {}", pkg.name, pkg.version).as_bytes());
+		}
+		input("build", build.as_bytes());
+		input("host", host.as_bytes());
+		input("target", target.as_bytes());
+		Ok(Some(to_hex(&hasher.finalize())))
+	}
+
+	/// Sets the input hash recorded in the package's metadata. See [`Self::input_hash`].
+	pub fn set_input_hash(&mut self, hash: String) {
+		self.build_desc.package.input_hash = Some(hash);
+	}
+
+	/// Sets the compression format of the package's archive.
+	pub fn set_archive_format(&mut self, format: ArchiveFormat) {
+		self.build_desc.package.archive_format = format;
+	}
+
+	/// Tells whether `repo` holds the package, built from the inputs with the given `hash`.
+	pub fn is_built(&self, repo: &Repository, arch: &str, hash: &str) -> Result<bool> {
+		let pkg = &self.build_desc.package;
+		let built = repo.get_package(arch, &pkg.name, &pkg.version)?;
+		Ok(built
+			.is_some_and(|p| p.input_hash.as_deref() == Some(hash) && repo.is_in_cache(arch, &p)))
+	}
+}
+
+impl BuildProcess {
+	/// Creates a new instance, with the directories of the build.
+	///
+	/// Arguments:
+	/// - `inputs` is the inputs of the build. If the package is built in an existing system root,
+	///   they must have been read accordingly
 	/// - `install_path` is the path to the install directory. If `None`, a directory is created.
 	/// - `work_dir` is the directory where build directories are located
-	/// - `chroot` for building in chroot environment
-	pub async fn new(
-		input_path: PathBuf,
+	/// - `isolation` is the isolation of hooks from the host
+	pub fn new(
+		inputs: BuildInputs,
 		install_path: Option<PathBuf>,
 		work_dir: &Path,
-		chroot: bool,
+		isolation: Isolation,
 	) -> Result<Self> {
 		// TODO replace root user check by CAP_SYS_CHROOT and CAP_MKNOD when implemented in kernel
-		if chroot && get_euid() != 0 {
+		if isolation == Isolation::Chroot && get_euid() != 0 {
 			bail!("--chroot requires root privileges!");
 		}
-		let build_desc_path = input_path.join("metadata.toml");
-		let build_desc = fs::read_to_string(build_desc_path)?;
-		let build_desc = toml::from_str::<BuildDescriptor>(&build_desc)?;
-		build_desc.package.validate()?;
+		let BuildInputs {
+			input_path,
+			build_desc,
+			features,
+			check,
+			build_deps,
+		} = inputs;
+		let pkg_dir_name = format!("{}-{}", build_desc.package.name, build_desc.package.version);
+		let log = BuildLog::create(work_dir, &pkg_dir_name)?;
+		log.write(format!(
+			"Package `{pkg_dir_name}`; Isolation: {isolation:?}; Features: {features:?}; Check: \
+			 {check}"
+		));
+		if let Some(network) = &build_desc.network {
+			if isolation != Isolation::None {
+				println!(
+					"[WARN] Network access is granted to the build: {}",
+					network.reason
+				);
+			}
+		}
 
-		let sysroot_exists = install_path.is_some();
-		let (build_dir, install_path, sysroot) = if chroot {
+		let (build_dir, install_path, sysroot) = if isolation != Isolation::None {
 			let sysroot = create_tmp_dir(work_dir)?;
-			let pkg_dir_name =
-				format!("{}-{}", build_desc.package.name, build_desc.package.version);
 			let build_dir = sysroot.join("usr/src").join(&pkg_dir_name);
 			let install_dir = sysroot.join("var/lib").join(pkg_dir_name);
+			// Both directories are mount points in the sandbox
+			fs::create_dir_all(&build_dir)?;
+			fs::create_dir_all(&install_dir)?;
 			(build_dir, install_dir, sysroot)
 		} else {
 			(
@@ -191,9 +475,6 @@ impl BuildProcess {
 					.unwrap_or_else(|| create_tmp_dir(work_dir))?,
 			)
 		};
-		if !sysroot_exists {
-			create_sysroot(&sysroot, &input_path, &build_desc.package).await?;
-		}
 
 		Ok(Self {
 			input_path,
@@ -201,10 +482,36 @@ impl BuildProcess {
 			build_dir,
 			install_path,
 			sysroot,
-			chroot,
+			isolation,
+			features,
+			check,
+			log,
+			build_deps,
 		})
 	}
 
+	/// Returns the log of the build.
+	pub fn log(&self) -> &BuildLog {
+		&self.log
+	}
+
+	/// Creates the sysroot, with the package's build dependencies installed.
+	///
+	/// If the sysroot was given to [`Self::new`], the function does nothing.
+	pub async fn prepare_sysroot(&self) -> Result<()> {
+		if let Some(deps) = &self.build_deps {
+			create_sysroot(
+				&self.sysroot,
+				&self.input_path,
+				deps,
+				self.isolation,
+				self.check,
+			)
+			.await?;
+		}
+		Ok(())
+	}
+
 	/// Fetches resources required to build the package.
 	pub async fn fetch_sources(&self) -> Result<()> {
 		let build_dir = Arc::new(self.build_dir.clone());
@@ -215,7 +522,8 @@ impl BuildProcess {
 			.cloned()
 			.map(move |s| {
 				let build_dir = build_dir.clone();
-				tokio::spawn(async move { s.fetch(&build_dir).await })
+				let log = self.log.clone();
+				tokio::spawn(async move { s.fetch(&build_dir, &log).await })
 			})
 			.collect::<Vec<_>>();
 		for f in futures {
@@ -224,7 +532,56 @@ impl BuildProcess {
 		Ok(())
 	}
 
-	/// Builds the package.
+	/// Applies the patches of the package to its sources, in order, then records them in the
+	/// package's metadata.
+	///
+	/// This function must be called after the sources have been fetched.
+	pub fn apply_patches(&mut self) -> Result<()> {
+		let input_path = fs::canonicalize(&self.input_path)?;
+		for patch in &self.build_desc.patch {
+			let path = input_path.join(&patch.path);
+			let name = patch
+				.path
+				.file_name()
+				.map(|n| n.to_string_lossy().into_owned())
+				.ok_or_else(|| anyhow!("invalid patch path `{}`", patch.path.display()))?;
+			let dir = concat_paths(&self.build_dir, &patch.location);
+			self.log.info(format_args!("Apply patch `{name}`"));
+			// Rejected hunks are written to a temporary file to be reported
+			let (reject_path, mut reject_file) = create_tmp_file(&self.build_dir)?;
+			let output = Command::new("patch")
+				.arg("--batch")
+				.arg("--forward")
+				.arg("--no-backup-if-mismatch")
+				.arg(format!("-p{}", patch.strip))
+				.arg("-d")
+				.arg(&dir)
+				.arg("-i")
+				.arg(&path)
+				.arg("-r")
+				.arg(&reject_path)
+				.output()?;
+			let mut rejected = String::new();
+			reject_file.read_to_string(&mut rejected)?;
+			fs::remove_file(&reject_path)?;
+			self.log.write(&output.stdout);
+			self.log.write(&output.stderr);
+			self.log.write(&rejected);
+			if !output.status.success() {
+				let stdout = String::from_utf8_lossy(&output.stdout);
+				let stderr = String::from_utf8_lossy(&output.stderr);
+				bail!("cannot apply patch `{name}`:\n{stdout}{stderr}{rejected}");
+			}
+			let sha256 = sha256_file(&path)?;
+			self.build_desc.package.patches.push(AppliedPatch {
+				name,
+				sha256,
+			});
+		}
+		Ok(())
+	}
+
+	/// Runs the hook with the given `name`.
 	///
 	/// Arguments:
 	/// - `jobs` is the number of concurrent jobs.
@@ -232,16 +589,24 @@ impl BuildProcess {
 	/// - `target` is the triplet of the target machine.
 	///
 	/// On success, the function returns `true`.
-	pub fn build(&self, jobs: usize, build: &str, host: &str, target: &str) -> io::Result<bool> {
-		let hook_path = if self.chroot {
-			PathBuf::from("/bin/build-hook")
+	fn run_hook(
+		&self,
+		name: &str,
+		jobs: usize,
+		build: &str,
+		host: &str,
+		target: &str,
+	) -> io::Result<bool> {
+		let isolated = self.isolation != Isolation::None;
+		let hook_path = if isolated {
+			Path::new("/bin").join(name)
 		} else {
-			get_build_hook_path(&self.input_path)?
+			get_hook_path(&self.input_path, name)?
 		};
 		// TODO refactor
-		// In chroot mode, paths exposed to the build hook must be relative to the
-		// chroot root
-		let (sysroot_env, install_path_env): (PathBuf, PathBuf) = if self.chroot {
+		// When isolated, paths exposed to the build hook must be relative to the
+		// sysroot
+		let (sysroot_env, install_path_env): (PathBuf, PathBuf) = if isolated {
 			let sysroot = PathBuf::from("/");
 			let install_path = Path::new("/").join(
 				self.install_path
@@ -252,25 +617,90 @@ impl BuildProcess {
 		} else {
 			(self.sysroot.clone(), self.install_path.clone())
 		};
+		// Sources are already fetched: isolated hooks do not need network access
+		let network = self.build_desc.network.is_some();
 		let mut cmd = Command::new(hook_path);
-		if self.chroot {
-			let sysroot = self.sysroot.clone();
-			unsafe {
-				cmd.pre_exec(move || chroot(&sysroot));
+		match self.isolation {
+			Isolation::None => {}
+			Isolation::Chroot => {
+				let sysroot = self.sysroot.clone();
+				unsafe {
+					cmd.pre_exec(move || {
+						if !network {
+							sandbox::isolate_network()?;
+						}
+						chroot(&sysroot)
+					});
+				}
+			}
+			Isolation::Sandbox => {
+				let cwd = Path::new("/").join(
+					self.build_dir
+						.strip_prefix(&self.sysroot)
+						.unwrap_or(&self.build_dir),
+				);
+				let sandbox = Sandbox::new(
+					&self.sysroot,
+					&[&self.build_dir, &self.install_path],
+					&cwd,
+					network,
+				)?;
+				unsafe {
+					cmd.pre_exec(move || sandbox.enter());
+				}
 			}
 		}
-		cmd.env("BUILD", build)
-			.env("HOST", host)
-			.env("TARGET", target)
-			.env("SYSROOT", &sysroot_env)
-			.env("INSTALL_PATH", &install_path_env)
-			.env("PKG_NAME", &self.build_desc.package.name)
-			.env("PKG_VERSION", self.build_desc.package.version.to_string())
-			.env("PKG_DESC", &self.build_desc.package.description)
-			.env("JOBS", jobs.to_string())
-			.current_dir(&self.build_dir)
-			.status()
-			.map(|s| s.success())
+		let vars: [(&str, OsString); 10] = [
+			("BUILD", build.into()),
+			("HOST", host.into()),
+			("TARGET", target.into()),
+			("SYSROOT", sysroot_env.into()),
+			("INSTALL_PATH", install_path_env.into()),
+			("PKG_NAME", (&self.build_desc.package.name).into()),
+			(
+				"PKG_VERSION",
+				self.build_desc.package.version.to_string().into(),
+			),
+			("PKG_DESC", (&self.build_desc.package.description).into()),
+			("JOBS", jobs.to_string().into()),
+			("FEATURES", self.features.join(" ").into()),
+		];
+		self.log.write(format!("Run hook `{name}`"));
+		// The hook inherits the environment of the builder, in addition to the variables above
+		let mut effective_env: BTreeMap<OsString, OsString> = env::vars_os().collect();
+		effective_env.extend(vars.iter().map(|(n, v)| (n.into(), v.clone())));
+		self.log.env(&effective_env);
+		cmd.envs(vars).current_dir(&self.build_dir);
+		self.log.run(&mut cmd).map(|s| s.success())
+	}
+
+	/// Builds the package.
+	///
+	/// Arguments:
+	/// - `jobs` is the number of concurrent jobs.
+	/// - `host` is the triplet of the host machine.
+	/// - `target` is the triplet of the target machine.
+	///
+	/// On success, the function returns `true`.
+	pub fn build(&self, jobs: usize, build: &str, host: &str, target: &str) -> io::Result<bool> {
+		self.run_hook(BUILD_HOOK, jobs, build, host, target)
+	}
+
+	/// Runs the package's test suite, after it has been built.
+	///
+	/// The arguments are the same as for [`Self::build`].
+	///
+	/// If checks are disabled or if the package has no check hook, the function does nothing and
+	/// returns `true`.
+	pub fn check(&self, jobs: usize, build: &str, host: &str, target: &str) -> io::Result<bool> {
+		if !self.check {
+			return Ok(true);
+		}
+		if !get_hook_path(&self.input_path, CHECK_HOOK)?.exists() {
+			self.log.info("No check hook, skipping checks");
+			return Ok(true);
+		}
+		self.run_hook(CHECK_HOOK, jobs, build, host, target)
 	}
 
 	/// Writes the package's metadata to the repository
@@ -280,30 +710,37 @@ impl BuildProcess {
 			&self.build_desc.package.name,
 			&self.build_desc.package.version,
 		);
-		// Make sure the parent directory exists
-		fs::create_dir_all(path.parent().unwrap())?;
+		let parent = path.parent().unwrap();
 		// Create metadata
 		let metadata = toml::to_string(&self.build_desc.package)?;
-		fs::write(path, metadata)?;
+		// Write to a temporary file first so that the index is never built from a partially
+		// written file while other packages are being built
+		let (tmp_path, mut file) = create_tmp_file(parent)?;
+		file.write_all(metadata.as_bytes())?;
+		fs::rename(tmp_path, path)?;
 		Ok(())
 	}
 
 	/// Creates the archive of the package after being build.
-	pub fn create_archive(&self, repo: &Repository, arch: &str) -> io::Result<()> {
-		let output_path = repo.get_archive_path(
-			arch,
-			&self.build_desc.package.name,
-			&self.build_desc.package.version,
-		);
+	///
+	/// The archive is reproducible, modification times being clamped to `epoch` if set.
+	pub fn create_archive(
+		&self,
+		repo: &Repository,
+		arch: &str,
+		epoch: Option<u64>,
+	) -> io::Result<()> {
+		let package = &self.build_desc.package;
+		let output_path = repo.get_archive_path(arch, package);
 		let build_desc_path = self.input_path.join("metadata.toml");
-		let archive = File::create(output_path)?;
-		let enc = GzEncoder::new(archive, Compression::default());
-		let mut tar = tar::Builder::new(enc);
-		tar.follow_symlinks(false);
-		tar.append_path_with_name(build_desc_path, "metadata.toml")?;
-		tar.append_dir_all("data", &self.install_path)?;
-		// TODO add install/update/remove hooks
-		tar.finish()
+		archive::write(
+			&output_path,
+			package.archive_format,
+			&build_desc_path,
+			&self.install_path,
+			self.build_desc.config.as_ref(),
+			epoch,
+		)
 	}
 
 	/// Cleans files created by the build process.
@@ -317,3 +754,92 @@ impl BuildProcess {
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::{tokio::runtime::Runtime, util::create_tmp_dir};
+
+	#[test]
+	fn host_env_lock() {
+		let sysroot = create_tmp_dir(env::temp_dir()).unwrap();
+		let lock_path = Environment::lock_path(&sysroot.canonicalize().unwrap());
+		let rt = Runtime::new().unwrap();
+		let open = |isolation| {
+			rt.block_on(open_host_env(&sysroot, "x86_64", isolation))
+				.unwrap()
+		};
+		let env = open(Isolation::None);
+		assert!(lock_path.exists());
+		drop(env);
+		assert!(!lock_path.exists());
+		// A sandboxed build does not need to lock the host environment, which another instance
+		// may hold
+		fs::write(&lock_path, "").unwrap();
+		let env = open(Isolation::Sandbox);
+		drop(env);
+		assert!(lock_path.exists());
+		fs::remove_dir_all(sysroot).unwrap();
+	}
+
+	/// Applies the given patches to a source tree containing `foo.txt`, with the content `a`.
+	///
+	/// `patches` is the list of patches, each described by its name, its `strip` value and its
+	/// content.
+	fn patch(patches: &[(&str, usize, &str)]) -> (Result<()>, String, Vec<String>) {
+		let dir = create_tmp_dir(env::temp_dir()).unwrap();
+		let input_path = dir.join("input");
+		fs::create_dir(&input_path).unwrap();
+		let mut desc =
+			"[package]\nname = \"foo\"\nversion = \"1.0\"\ndescription = \"\"\n".to_owned();
+		for (name, strip, content) in patches {
+			fs::write(input_path.join(name), content).unwrap();
+			desc.push_str(&format!("[[patch]]\npath = \"{name}\"\nstrip = {strip}\n"));
+		}
+		fs::write(input_path.join("metadata.toml"), desc).unwrap();
+		let inputs = Runtime::new()
+			.unwrap()
+			.block_on(BuildInputs::read(
+				input_path,
+				true,
+				&[],
+				true,
+				false,
+				Isolation::None,
+			))
+			.unwrap();
+		let mut process =
+			BuildProcess::new(inputs, Some(dir.clone()), &dir, Isolation::None).unwrap();
+		fs::write(process.build_dir.join("foo.txt"), "a\n").unwrap();
+		let res = process.apply_patches();
+		let content = fs::read_to_string(process.build_dir.join("foo.txt")).unwrap();
+		let applied = process
+			.build_desc
+			.package
+			.patches
+			.iter()
+			.map(|p| p.name.clone())
+			.collect();
+		fs::remove_dir_all(dir).unwrap();
+		(res, content, applied)
+	}
+
+	#[test]
+	fn apply_patches() {
+		let a_to_b = "--- a/foo.txt\n+++ b/foo.txt\n@@ -1 +1 @@\n-a\n+b\n";
+		let b_to_c = "--- foo.txt\n+++ foo.txt\n@@ -1 +1 @@\n-b\n+c\n";
+		// Patches are applied in order, each with its own `strip` value
+		let (res, content, applied) = patch(&[("01.patch", 1, a_to_b), ("02.patch", 0, b_to_c)]);
+		res.unwrap();
+		assert_eq!(content, "c\n");
+		assert_eq!(applied, ["01.patch", "02.patch"]);
+		// A patch that has already been applied is rejected instead of being reverted
+		let (res, content, applied) = patch(&[("01.patch", 1, a_to_b), ("02.patch", 1, a_to_b)]);
+		let err = res.unwrap_err().to_string();
+		assert!(err.starts_with("cannot apply patch `02.patch`"));
+		// The rejected hunk is reported
+		assert!(err.contains("-a\n+b"));
+		assert_eq!(content, "b\n");
+		assert_eq!(applied, ["01.patch"]);
+	}
+}
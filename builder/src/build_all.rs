@@ -0,0 +1,462 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Building every package of a tree of build descriptors.
+//!
+//! Packages are built in the order given by their dependencies, independent packages being built
+//! concurrently. Each package is built by a separate `blimp-builder build` process, and written
+//! to an output repository, from which the packages depending on it are installed into their
+//! build sysroot.
+
+use crate::{
+	desc::{BuildDescriptor, Source},
+	get_arch, log, BuildAllArgs,
+};
+use common::{
+	anyhow::{anyhow, bail, Result},
+	package::{DependencyType, Package},
+	repository::{Index, Repository},
+	util::create_tmp_file,
+};
+use std::{
+	env, fs,
+	io::{self, Write},
+	path::{Path, PathBuf},
+	process::Command,
+	sync::mpsc,
+	thread,
+};
+
+/// The name of the file containing a package's build descriptor.
+const DESCRIPTOR: &str = "metadata.toml";
+
+/// A package to be built.
+struct Node {
+	/// The path to the directory containing the package's build descriptor.
+	path: PathBuf,
+	/// The package's metadata.
+	package: Package,
+	/// Tells whether all the sources of the package are pinned. See [`Source::is_pinned`].
+	pinned: bool,
+	/// The indexes of the nodes the package depends on.
+	deps: Vec<usize>,
+}
+
+/// The state of a package during the build.
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum State {
+	/// The package is waiting for its dependencies to be built.
+	Pending,
+	/// The package is being built.
+	Running,
+	/// The package has been built.
+	Done,
+	/// The build of the package failed.
+	Failed,
+	/// The package cannot be built because one of its dependencies failed.
+	Blocked,
+}
+
+/// Inserts into `paths` the paths of all the directories under `dir` containing a build
+/// descriptor.
+///
+/// Directories containing a build descriptor are not searched further.
+fn find_descriptors(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+	if dir.join(DESCRIPTOR).is_file() {
+		paths.push(dir.to_path_buf());
+		return Ok(());
+	}
+	for ent in fs::read_dir(dir)? {
+		let ent = ent?;
+		if ent.file_type()?.is_dir() {
+			find_descriptors(&ent.path(), paths)?;
+		}
+	}
+	Ok(())
+}
+
+/// Reads the build descriptor in the directory at `path`, with its default features enabled.
+fn read_descriptor(path: &Path) -> Result<BuildDescriptor> {
+	let desc_path = path.join(DESCRIPTOR);
+	let content = fs::read_to_string(&desc_path)
+		.map_err(|e| anyhow!("cannot read `{}`: {e}", desc_path.display()))?;
+	let mut desc: BuildDescriptor = toml::from_str(&content)
+		.map_err(|e| anyhow!("invalid descriptor `{}`: {e}", desc_path.display()))?;
+	desc.enable_features(&[], true)?;
+	Ok(desc)
+}
+
+/// Reads the package described in the directory at `path`, with its default features enabled.
+pub(crate) fn read_package(path: &Path) -> Result<Package> {
+	Ok(read_descriptor(path)?.package)
+}
+
+/// Links each node to the nodes it depends on.
+///
+/// Dependencies that are not part of the tree are expected to be available from repositories. If
+/// `check` is set, check dependencies are taken into account.
+fn link(nodes: &mut [Node], check: bool) {
+	let deps: Vec<Vec<usize>> = nodes
+		.iter()
+		.enumerate()
+		.map(|(i, node)| {
+			let mut deps: Vec<usize> = node
+				.package
+				.deps
+				.iter()
+				.filter(|d| match d.dep_type {
+					DependencyType::Optional => false,
+					DependencyType::Check => check,
+					_ => true,
+				})
+				.flat_map(|d| {
+					nodes.iter().enumerate().filter_map(move |(j, n)| {
+						(j != i && n.package.satisfies(&d.name, &d.version_constraint))
+							.then_some(j)
+					})
+				})
+				.collect();
+			deps.sort_unstable();
+			deps.dedup();
+			deps
+		})
+		.collect();
+	for (node, deps) in nodes.iter_mut().zip(deps) {
+		node.deps = deps;
+	}
+}
+
+/// Returns the indexes of the nodes which cannot be ordered because they are part of, or depend
+/// on, a dependency cycle.
+fn find_cycle(nodes: &[Node]) -> Vec<usize> {
+	let mut ordered = vec![false; nodes.len()];
+	loop {
+		let mut progress = false;
+		for (i, node) in nodes.iter().enumerate() {
+			if !ordered[i] && node.deps.iter().all(|d| ordered[*d]) {
+				ordered[i] = true;
+				progress = true;
+			}
+		}
+		if !progress {
+			break;
+		}
+	}
+	(0..nodes.len()).filter(|i| !ordered[*i]).collect()
+}
+
+/// Returns the state of each node before the build starts.
+///
+/// The build of a package whose sources are pinned is skipped by the build itself if its inputs
+/// did not change. The content of other sources is unknown until they are fetched: such packages
+/// are not built again if `repo` already holds them, unless `force` is set.
+fn initial_states(nodes: &[Node], repo: &Repository, arch: &str, force: bool) -> Vec<State> {
+	nodes
+		.iter()
+		.map(|node| {
+			let pkg = &node.package;
+			let built = !force
+				&& !node.pinned
+				&& repo.is_in_cache(arch, pkg)
+				&& repo
+					.get_metadata_path(arch, &pkg.name, &pkg.version)
+					.exists();
+			if built {
+				println!("[INFO] `{}` {} is already built", pkg.name, pkg.version);
+				State::Done
+			} else {
+				State::Pending
+			}
+		})
+		.collect()
+}
+
+/// Writes the index of the local repository at `path`, listing every package it contains.
+fn write_index(path: &Path) -> Result<()> {
+	let mut index = Index::default();
+	let archs = match fs::read_dir(path.join("dist")) {
+		Ok(archs) => archs,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+		Err(e) => return Err(e.into()),
+	};
+	for arch in archs {
+		let arch = arch?;
+		let arch_name = arch.file_name().to_string_lossy().into_owned();
+		for ent in fs::read_dir(arch.path())? {
+			let ent_path = ent?.path();
+			if ent_path.extension().is_none_or(|ext| ext != "meta") {
+				continue;
+			}
+			if let Some(pkg) = Package::from_file(&ent_path)? {
+				let ent = index.arch.entry(arch_name.clone()).or_default();
+				ent.package.push(pkg);
+			}
+		}
+	}
+	let repo = Repository::local(path.to_path_buf());
+	// Packages being built read the index to find their dependencies
+	let (tmp_path, mut file) = create_tmp_file(path)?;
+	file.write_all(toml::to_string(&index)?.as_bytes())?;
+	fs::rename(tmp_path, repo.get_index_path())?;
+	Ok(())
+}
+
+/// Returns the command building the package of `node` into the output repository.
+fn build_command(args: &BuildAllArgs, node: &Node, to: &Path) -> Result<Command> {
+	let mut cmd = Command::new(env::current_exe()?);
+	cmd.arg("build")
+		.arg("--from")
+		.arg(&node.path)
+		.arg("--to")
+		.arg(to)
+		.arg("--package")
+		.arg("--work-dir")
+		.arg(&args.work_dir);
+	let triplets = [
+		("--build", &args.build),
+		("--host", &args.host),
+		("--target", &args.target),
+	];
+	for (flag, triplet) in triplets {
+		if let Some(triplet) = triplet {
+			cmd.arg(flag).arg(triplet);
+		}
+	}
+	if let Some(jobs) = args.jobs {
+		cmd.arg("--jobs").arg(jobs.to_string());
+	}
+	if args.chroot {
+		cmd.arg("--chroot");
+	}
+	if args.sandbox {
+		cmd.arg("--sandbox");
+	}
+	if args.check {
+		cmd.arg("--check");
+	}
+	if !args.archive_format.is_default() {
+		cmd.arg("--archive-format")
+			.arg(args.archive_format.to_string());
+	}
+	if args.ignore_check {
+		cmd.arg("--ignore-check");
+	}
+	if args.force {
+		cmd.arg("--force");
+	}
+	// Make the packages built previously available to the build
+	let mut local_repos = to.as_os_str().to_owned();
+	if let Some(repos) = env::var_os("LOCAL_REPO") {
+		local_repos.push(":");
+		local_repos.push(repos);
+	}
+	cmd.env("LOCAL_REPO", local_repos);
+	Ok(cmd)
+}
+
+/// Builds every package described under `args.from`.
+///
+/// `build` is the triplet of the machine on which packages are built.
+pub fn build_all(args: &BuildAllArgs, build: &str) -> Result<()> {
+	let mut paths = vec![];
+	find_descriptors(&args.from, &mut paths)?;
+	paths.sort_unstable();
+	let mut nodes = paths
+		.into_iter()
+		.map(|path| {
+			let desc = read_descriptor(&path)?;
+			Ok(Node {
+				pinned: desc.source.iter().all(Source::is_pinned),
+				package: desc.package,
+				path,
+				deps: vec![],
+			})
+		})
+		.collect::<Result<Vec<_>>>()?;
+	if nodes.is_empty() {
+		bail!("no build descriptor found in `{}`", args.from.display());
+	}
+	link(&mut nodes, args.check);
+	let cycle = find_cycle(&nodes);
+	if !cycle.is_empty() {
+		let names: Vec<_> = cycle
+			.iter()
+			.map(|i| nodes[*i].package.name.as_str())
+			.collect();
+		bail!("dependency cycle between packages: {}", names.join(", "));
+	}
+
+	fs::create_dir_all(&args.to)?;
+	let to = args.to.canonicalize()?;
+	let repo = Repository::local(to.clone());
+	let arch = get_arch(args.host.as_deref().unwrap_or(build));
+	let mut states = initial_states(&nodes, &repo, arch, args.force);
+	write_index(&to)?;
+
+	let parallel = args.parallel.unwrap_or_else(|| {
+		let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+		args.jobs.map_or(1, |jobs| (cpus / jobs.max(1)).max(1))
+	});
+	let (tx, rx) = mpsc::channel();
+	let mut running = 0;
+	loop {
+		// Packages depending on a failed package cannot be built
+		let mut changed = true;
+		while changed {
+			changed = false;
+			for i in 0..nodes.len() {
+				let blocked = nodes[i]
+					.deps
+					.iter()
+					.any(|d| matches!(states[*d], State::Failed | State::Blocked));
+				if states[i] == State::Pending && blocked {
+					states[i] = State::Blocked;
+					changed = true;
+				}
+			}
+		}
+		// Start the packages whose dependencies are built
+		while running < parallel {
+			let ready = (0..nodes.len()).find(|i| {
+				states[*i] == State::Pending
+					&& nodes[*i].deps.iter().all(|d| states[*d] == State::Done)
+			});
+			let Some(i) = ready else {
+				break;
+			};
+			let pkg = &nodes[i].package;
+			println!("[INFO] Build `{}` {}...", pkg.name, pkg.version);
+			let mut cmd = build_command(args, &nodes[i], &to)?;
+			let tx = tx.clone();
+			thread::spawn(move || {
+				let res = cmd.status();
+				// The receiver lives until every build is over
+				let _ = tx.send((i, res));
+			});
+			states[i] = State::Running;
+			running += 1;
+		}
+		if running == 0 {
+			break;
+		}
+		let (i, res) = rx.recv()?;
+		running -= 1;
+		let name = &nodes[i].package.name;
+		let log_path = log::get_path(
+			&args.work_dir,
+			&format!("{name}-{}", nodes[i].package.version),
+		);
+		states[i] = match res {
+			Ok(status) if status.success() => {
+				write_index(&to)?;
+				println!("[INFO] `{name}` built");
+				State::Done
+			}
+			Ok(status) => {
+				eprintln!(
+					"[ERROR] Build of `{name}` failed: {status}; see `{}`",
+					log_path.display()
+				);
+				State::Failed
+			}
+			Err(e) => {
+				eprintln!("[ERROR] Cannot build `{name}`: {e}");
+				State::Failed
+			}
+		};
+	}
+
+	let list = |state: State| {
+		let names: Vec<_> = nodes
+			.iter()
+			.zip(&states)
+			.filter(|(_, s)| **s == state)
+			.map(|(n, _)| n.package.name.as_str())
+			.collect();
+		names.join(", ")
+	};
+	let failed = list(State::Failed);
+	if !failed.is_empty() {
+		let blocked = list(State::Blocked);
+		if blocked.is_empty() {
+			bail!("failed to build: {failed}");
+		}
+		bail!("failed to build: {failed}; not built because of failed dependencies: {blocked}");
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::util::create_tmp_dir;
+
+	fn node(name: &str, deps: &[(&str, &str)]) -> Node {
+		let mut desc = format!("name = \"{name}\"\nversion = \"1.0\"\ndescription = \"\"\n");
+		for (dep_type, dep) in deps {
+			desc.push_str(&format!(
+				"[[dep]]\ntype = \"{dep_type}\"\nname = \"{dep}\"\nversion = \"*\"\n"
+			));
+		}
+		Node {
+			path: PathBuf::new(),
+			package: Package::parse(&desc).unwrap(),
+			pinned: true,
+			deps: vec![],
+		}
+	}
+
+	#[test]
+	fn graph() {
+		let mut nodes = vec![
+			node("gcc", &[("build", "binutils"), ("run", "musl")]),
+			node("binutils", &[("build", "zlib")]),
+			node("musl", &[("check", "gcc"), ("optional", "gcc")]),
+			node("zlib", &[("build", "make")]),
+		];
+		link(&mut nodes, false);
+		let deps: Vec<_> = nodes.iter().map(|n| n.deps.clone()).collect();
+		assert_eq!(deps, [vec![1, 2], vec![3], vec![], vec![]]);
+		assert!(find_cycle(&nodes).is_empty());
+		// `musl` depends on `gcc` to be checked
+		link(&mut nodes, true);
+		assert_eq!(find_cycle(&nodes), [0, 2]);
+	}
+
+	#[test]
+	fn skip_built() {
+		let path = create_tmp_dir(env::temp_dir()).unwrap();
+		let repo = Repository::local(path.clone());
+		let mut nodes = vec![node("pinned", &[]), node("unpinned", &[])];
+		nodes[1].pinned = false;
+		let states = |force| initial_states(&nodes, &repo, "test", force);
+		// Nothing is built yet
+		assert!(states(false) == [State::Pending, State::Pending]);
+		for node in &nodes {
+			let pkg = &node.package;
+			let meta = repo.get_metadata_path("test", &pkg.name, &pkg.version);
+			fs::create_dir_all(meta.parent().unwrap()).unwrap();
+			fs::write(meta, "").unwrap();
+			fs::write(repo.get_archive_path("test", pkg), "").unwrap();
+		}
+		// The build of the pinned package checks its input hash by itself
+		assert!(states(false) == [State::Pending, State::Done]);
+		assert!(states(true) == [State::Pending, State::Pending]);
+		fs::remove_dir_all(path).unwrap();
+	}
+}
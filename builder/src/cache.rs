@@ -107,14 +107,34 @@ impl CacheEntry {
 	}
 }
 
+/// Encodes `key` into a string that can be used as a file name.
+fn encode_key(key: &[u8]) -> String {
+	// `/` causes issues with paths. Replace it by `-` which is not in the base64 characters set
+	BASE64_STANDARD.encode(key).replace('/', "-")
+}
+
+/// Returns the path to the cached clone of the Git repository at `url`, along with a lock which
+/// must be held while the clone is in use.
+///
+/// The clone does not necessarily exist yet.
+pub fn git_clone(url: &str) -> io::Result<(PathBuf, FileLock)> {
+	let dir_path = cache_directory()?.join("git");
+	fs::create_dir_all(&dir_path)?;
+	let encoded_key = encode_key(url.as_bytes());
+	// `.` is not part of the base64 character set
+	let lock_path = dir_path.join(format!("{encoded_key}.lock"));
+	let opt = FileOptions::new().write(true).create(true);
+	let lock = FileLock::lock(lock_path, true, opt)?;
+	Ok((dir_path.join(encoded_key), lock))
+}
+
 /// Retrieves or insert the entry with the given `key`.
 ///
 /// The function returns the entry's file, along with a boolean indicating whether the file existed
 /// before.
 pub fn get_or_insert(key: &[u8]) -> io::Result<CacheEntry> {
 	let dir_path = cache_directory()?;
-	// `/` causes issues with paths. Replace it by `-` which is not in the base64 characters set
-	let encoded_key = BASE64_STANDARD.encode(key).replace('/', "-");
+	let encoded_key = encode_key(key);
 	let path = dir_path.join(&encoded_key);
 	// Open file
 	let opt = FileOptions::new().read(true).write(true).create(true);
@@ -0,0 +1,69 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Filling in the checksums of the sources of a build descriptor.
+
+use crate::desc::BuildDescriptor;
+use common::anyhow::{anyhow, bail, Result};
+use std::{fs, path::Path};
+use toml_edit::{value, DocumentMut};
+
+/// Computes the checksums of the tarballs among the sources of the package at `input_path`, and
+/// writes them to its build descriptor.
+///
+/// If `sha512` is set, SHA-512 checksums are written instead of SHA-256 ones.
+///
+/// The rest of the build descriptor, including comments and formatting, is left untouched.
+pub async fn fill(input_path: &Path, sha512: bool) -> Result<()> {
+	let path = input_path.join("metadata.toml");
+	let content =
+		fs::read_to_string(&path).map_err(|e| anyhow!("cannot read `{}`: {e}", path.display()))?;
+	let desc: BuildDescriptor = toml::from_str(&content)
+		.map_err(|e| anyhow!("invalid descriptor `{}`: {e}", path.display()))?;
+	let mut doc: DocumentMut = content.parse()?;
+	let Some(tables) = doc
+		.get_mut("source")
+		.and_then(|s| s.as_array_of_tables_mut())
+	else {
+		println!("No source to checksum");
+		return Ok(());
+	};
+	if tables.len() != desc.source.len() {
+		bail!("sources must be written as an array of tables (`[[source]]`)");
+	}
+	let key = if sha512 { "sha512" } else { "sha256" };
+	for (source, table) in desc.source.iter().zip(tables.iter_mut()) {
+		let Some(checksums) = source.compute_checksums().await? else {
+			continue;
+		};
+		let hash = if sha512 {
+			checksums.sha512
+		} else {
+			checksums.sha256
+		};
+		let hash = hash.unwrap();
+		let previous = table.get(key).and_then(|v| v.as_str());
+		if previous.is_some_and(|p| !p.eq_ignore_ascii_case(&hash)) {
+			eprintln!("warning: `{}`: {key} changed", source.remote());
+		}
+		println!("`{}`: {key} = {hash}", source.remote());
+		table.insert(key, value(hash));
+	}
+	fs::write(&path, doc.to_string())?;
+	Ok(())
+}
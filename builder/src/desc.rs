@@ -27,14 +27,100 @@
 //! Tarballs may contain a single directory in which all files are present. "Unwrapping" is the
 //! action of moving all the files out of this directory while decompressing the archive.
 
-use common::{anyhow::Result, package::Package};
+#[cfg(feature = "network")]
+use crate::{cache, log::BuildLog};
+use common::{
+	anyhow::{anyhow, bail, Result},
+	package::{Dependency, Package},
+	util::to_hex,
+};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
 use std::{
-	fs,
+	collections::BTreeMap,
+	fmt, fs,
 	fs::File,
+	io,
+	io::{Read, Seek, SeekFrom},
 	path::{Path, PathBuf},
 };
 
+/// Checksums of a source file.
+///
+/// Each specified checksum is verified before the file is used.
+#[derive(Clone, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Checksums {
+	/// The SHA-256 hash of the file, in hexadecimal.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sha256: Option<String>,
+	/// The SHA-512 hash of the file, in hexadecimal.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sha512: Option<String>,
+}
+
+impl Checksums {
+	/// Computes all the checksums of the content of `file`.
+	pub fn compute(file: &mut File) -> io::Result<Self> {
+		file.seek(SeekFrom::Start(0))?;
+		let mut sha256 = Sha256::new();
+		let mut sha512 = Sha512::new();
+		let mut buf = [0; 4096];
+		loop {
+			let len = file.read(&mut buf)?;
+			if len == 0 {
+				break;
+			}
+			sha256.update(&buf[..len]);
+			sha512.update(&buf[..len]);
+		}
+		Ok(Self {
+			sha256: Some(to_hex(&sha256.finalize())),
+			sha512: Some(to_hex(&sha512.finalize())),
+		})
+	}
+
+	/// Tells whether no checksum is specified.
+	pub fn is_empty(&self) -> bool {
+		self.sha256.is_none() && self.sha512.is_none()
+	}
+
+	/// Verifies the content of `file` against the specified checksums.
+	///
+	/// If no checksum is specified, the function does nothing.
+	pub fn verify(&self, file: &mut File) -> Result<()> {
+		if self.is_empty() {
+			return Ok(());
+		}
+		let actual = Self::compute(file)?;
+		let checks = [
+			("sha256", &self.sha256, &actual.sha256),
+			("sha512", &self.sha512, &actual.sha512),
+		];
+		for (name, expected, actual) in checks {
+			let (Some(expected), Some(actual)) = (expected, actual) else {
+				continue;
+			};
+			if !expected.eq_ignore_ascii_case(actual) {
+				bail!("{name} mismatch: expected `{expected}`, got `{actual}`");
+			}
+		}
+		Ok(())
+	}
+
+	/// Returns the key identifying the file in the cache from its content, which is its strongest
+	/// checksum.
+	///
+	/// If no checksum is specified, the function returns `None`.
+	pub fn cache_key(&self) -> Option<String> {
+		if let Some(hash) = &self.sha512 {
+			return Some(format!("sha512:{}", hash.to_ascii_lowercase()));
+		}
+		self.sha256
+			.as_ref()
+			.map(|hash| format!("sha256:{}", hash.to_ascii_lowercase()))
+	}
+}
+
 /// Remote location of a source.
 #[derive(Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
 #[serde(untagged)]
@@ -43,8 +129,9 @@ pub enum SourceRemote {
 	Url {
 		/// The URL of the sources.
 		url: String,
-		// TODO add optional hash to check tarball
-		// TODO add option to fetch hash from URL?
+		/// The checksums of the tarball.
+		#[serde(flatten)]
+		checksums: Checksums,
 	},
 	/// Clone the given repository.
 	Git {
@@ -52,14 +139,181 @@ pub enum SourceRemote {
 		git_url: String,
 		/// The branch to clone from. If not specified, the default branch is used.
 		branch: Option<String>,
+		/// The tag to check out. Cannot be specified along with `branch`.
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		tag: Option<String>,
+		/// The full hash of the commit to check out. If a tag is specified too, it must point to
+		/// this commit.
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		commit: Option<String>,
 	},
 	/// Copy from a local path to a tarball or directory.
 	Local {
 		/// The local path.
 		path: PathBuf,
+		/// The checksums of the tarball. Checksums cannot be specified for a directory.
+		#[serde(flatten)]
+		checksums: Checksums,
 	},
 }
 
+impl fmt::Display for SourceRemote {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Url {
+				url, ..
+			} => write!(f, "{url}"),
+			Self::Git {
+				git_url, ..
+			} => write!(f, "{git_url}"),
+			Self::Local {
+				path, ..
+			} => write!(f, "{}", path.display()),
+		}
+	}
+}
+
+/// Returns the cache entry with the given `key`, downloading its content from `url` if it is not
+/// in cache.
+///
+/// The entry is not flushed to the cache, so that the caller can verify it beforehand.
+#[cfg(feature = "network")]
+async fn fetch_url(url: &str, key: &str, log: Option<&BuildLog>) -> Result<cache::CacheEntry> {
+	use common::download::DownloadTask;
+	let info = |msg: String| match log {
+		Some(log) => log.info(msg),
+		None => println!("[INFO] {msg}"),
+	};
+	let mut ent = cache::get_or_insert(key.as_bytes())?;
+	if !ent.cached() {
+		info(format!("Download `{url}`"));
+		let mut download_task = DownloadTask::new(url, ent.file()).await?;
+		while download_task.next().await? > 0 {}
+	} else {
+		info(format!("`{url}` is in cache"));
+	}
+	Ok(ent)
+}
+
+/// Runs `git` with the given arguments, in the repository at `repo`. The output of the command
+/// is written to `log`.
+///
+/// On success, the function returns the trimmed standard output. If the command fails, the
+/// function returns `None`.
+#[cfg(feature = "network")]
+fn git(repo: &Path, args: &[&str], log: &BuildLog) -> Result<Option<String>> {
+	use std::process::Command;
+	let (status, stdout) = log.output(Command::new("git").arg("-C").arg(repo).args(args))?;
+	let stdout = String::from_utf8_lossy(&stdout).trim().to_owned();
+	Ok(status.success().then_some(stdout))
+}
+
+/// Fetches the given revision of the Git repository at `url`, and copies it to `dest`.
+///
+/// Repositories are kept in the sources cache: pinned revisions which are already cached are not
+/// fetched again.
+///
+/// Arguments:
+/// - `branch` is the branch to fetch. If `None`, the default branch is used
+/// - `tag` is the tag to check out
+/// - `commit` is the full hash of the commit to check out. The function fails if the resulting
+///   `HEAD` is not this commit
+#[cfg(feature = "network")]
+fn fetch_git(
+	url: &str,
+	branch: Option<&str>,
+	tag: Option<&str>,
+	commit: Option<&str>,
+	dest: &Path,
+	log: &BuildLog,
+) -> Result<()> {
+	if branch.is_some() && tag.is_some() {
+		bail!("`{url}`: `branch` and `tag` cannot be specified at the same time");
+	}
+	if let Some(commit) = commit {
+		let valid =
+			matches!(commit.len(), 40 | 64) && commit.chars().all(|c| c.is_ascii_hexdigit());
+		if !valid {
+			bail!("`{url}`: `commit` must be a full commit hash, got `{commit}`");
+		}
+	}
+	let (repo, _lock) = cache::git_clone(url)?;
+	if !repo.join(".git").exists() {
+		fs::create_dir_all(&repo)?;
+		git(&repo, &["init", "-q"], log)?.ok_or_else(|| anyhow!("cannot create repository"))?;
+		git(&repo, &["remote", "add", "origin", url], log)?
+			.ok_or_else(|| anyhow!("cannot create repository"))?;
+	}
+	// The revision to check out
+	let rev = match (tag, commit) {
+		(Some(tag), _) => format!("refs/tags/{tag}^{{commit}}"),
+		(None, Some(commit)) => format!("{commit}^{{commit}}"),
+		(None, None) => "FETCH_HEAD".to_owned(),
+	};
+	// Branches move: they are always fetched again
+	let pinned = tag.is_some() || commit.is_some();
+	if pinned && git(&repo, &["rev-parse", "-q", "--verify", &rev], log)?.is_some() {
+		log.info(format!("`{url}` is in cache"));
+	} else {
+		log.info(format!("Fetch `{url}`"));
+		let refspec = match (tag, commit, branch) {
+			(Some(tag), ..) => format!("+refs/tags/{tag}:refs/tags/{tag}"),
+			(None, Some(commit), _) => commit.to_owned(),
+			(None, None, Some(branch)) => format!("refs/heads/{branch}"),
+			(None, None, None) => "HEAD".to_owned(),
+		};
+		let fetched = git(
+			&repo,
+			&["fetch", "-q", "--depth", "1", "origin", &refspec],
+			log,
+		)?;
+		if fetched.is_none() {
+			// Some servers do not allow fetching a commit from its hash: fetch everything instead
+			let mut args = vec![
+				"fetch",
+				"-q",
+				"--tags",
+				"origin",
+				"+refs/heads/*:refs/remotes/origin/*",
+			];
+			if repo.join(".git/shallow").exists() {
+				args.push("--unshallow");
+			}
+			git(&repo, &args, log)?.ok_or_else(|| anyhow!("cannot fetch `{url}`"))?;
+		}
+	}
+	git(&repo, &["checkout", "-q", "--force", "--detach", &rev], log)?
+		.ok_or_else(|| anyhow!("`{url}`: cannot check out `{rev}`"))?;
+	git(&repo, &["clean", "-q", "-d", "-f", "-x"], log)?
+		.ok_or_else(|| anyhow!("`{url}`: cannot clean repository"))?;
+	let head = git(&repo, &["rev-parse", "HEAD"], log)?
+		.ok_or_else(|| anyhow!("`{url}`: cannot read `HEAD`"))?;
+	if let Some(commit) = commit {
+		if !head.eq_ignore_ascii_case(commit) {
+			bail!("`{url}`: expected commit `{commit}`, got `{head}`");
+		}
+	}
+	log.info(format!("`{url}` is at commit `{head}`"));
+	// Copy the work tree only, without the repository's history
+	fs::create_dir_all(dest)?;
+	for ent in fs::read_dir(&repo)? {
+		let ent = ent?;
+		if ent.file_name() == ".git" {
+			continue;
+		}
+		let to = dest.join(ent.file_name());
+		let file_type = ent.file_type()?;
+		if file_type.is_dir() {
+			common::util::recursive_copy(&ent.path(), &to)?;
+		} else if file_type.is_symlink() {
+			std::os::unix::fs::symlink(fs::read_link(ent.path())?, &to)?;
+		} else {
+			fs::copy(ent.path(), &to)?;
+		}
+	}
+	Ok(())
+}
+
 /// Description of sources files, where to find them and where to place them for building.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct Source {
@@ -71,20 +325,91 @@ pub struct Source {
 }
 
 impl Source {
+	/// Returns the remote location of the source.
+	#[inline]
+	pub fn remote(&self) -> &SourceRemote {
+		&self.inner
+	}
+
+	/// Tells whether the content of the source is determined by its description, so that it
+	/// cannot change unless the description changes too.
+	///
+	/// This is the case of tarballs with checksums, and of Git sources checking out a tag or a
+	/// commit.
+	pub fn is_pinned(&self) -> bool {
+		match &self.inner {
+			SourceRemote::Url {
+				checksums, ..
+			}
+			| SourceRemote::Local {
+				checksums, ..
+			} => !checksums.is_empty(),
+			SourceRemote::Git {
+				tag,
+				commit,
+				..
+			} => tag.is_some() || commit.is_some(),
+		}
+	}
+
+	/// Computes the checksums of the source's tarball.
+	///
+	/// If the source is not a tarball, the function returns `None`.
+	pub async fn compute_checksums(&self) -> Result<Option<Checksums>> {
+		match &self.inner {
+			SourceRemote::Local {
+				path, ..
+			} => {
+				if fs::metadata(path)?.is_dir() {
+					return Ok(None);
+				}
+				let mut file = File::open(path)?;
+				Ok(Some(Checksums::compute(&mut file)?))
+			}
+			#[cfg(feature = "network")]
+			SourceRemote::Url {
+				url, ..
+			} => {
+				let mut ent = fetch_url(url, url, None).await?;
+				ent.flush()?;
+				Ok(Some(Checksums::compute(ent.file())?))
+			}
+			#[cfg(not(feature = "network"))]
+			SourceRemote::Url {
+				..
+			} => bail!("cannot download sources: feature `network` is not enabled"),
+			SourceRemote::Git {
+				..
+			} => Ok(None),
+		}
+	}
+
 	/// Fetches files from the source and decompress them if necessary.
 	///
+	/// Tarballs are verified against their checksums, if specified, before being decompressed.
+	///
 	/// Files are placed into the build directory `build_dir` according to the specified location.
-	pub async fn fetch(&self, build_dir: &Path) -> Result<()> {
+	/// Progress is written to `log`.
+	pub async fn fetch(&self, build_dir: &Path, log: &BuildLog) -> Result<()> {
 		let dest_path = common::util::concat_paths(build_dir, &self.location);
 		match &self.inner {
-			SourceRemote::Local { path } => {
-				println!("[INFO] Copy `{}`", path.display());
+			SourceRemote::Local {
+				path,
+				checksums,
+			} => {
+				log.info(format_args!("Copy `{}`", path.display()));
 				let metadata = fs::metadata(path)?;
 				if metadata.is_dir() {
+					if !checksums.is_empty() {
+						bail!("cannot verify checksums of directory `{}`", path.display());
+					}
 					common::util::recursive_copy(path, &dest_path)?;
 				} else {
 					// TODO decompress only if it is an actual archive
 					let mut file = File::open(path)?;
+					checksums
+						.verify(&mut file)
+						.map_err(|e| anyhow!("`{}`: {e}", path.display()))?;
 					common::util::decompress(&mut file, &dest_path)?;
 				}
 			}
@@ -97,50 +422,101 @@ impl Source {
 		match &self.inner {
 			SourceRemote::Url {
 				url,
+				checksums,
 			} => {
-				use crate::cache;
-				use common::download::DownloadTask;
-				// Check whether the file is in cache
-				let mut ent = cache::get_or_insert(url.as_bytes())?;
-				if !ent.cached() {
-					println!("[INFO] Download `{url}`");
-					let mut download_task = DownloadTask::new(url, ent.file()).await?;
-					while download_task.next().await? > 0 {}
-					ent.flush()?;
-				} else {
-					println!("[INFO] `{url}` is in cache");
-				}
-				// TODO check integrity with hash if specified
+				// When the content is known, it can be shared with other URLs
+				let key = checksums.cache_key().unwrap_or_else(|| url.clone());
+				let mut ent = fetch_url(url, &key, Some(log)).await?;
+				checksums
+					.verify(ent.file())
+					.map_err(|e| anyhow!("`{url}`: {e}"))?;
+				ent.flush()?;
 				common::util::decompress(ent.file(), &dest_path)?;
 			}
 			SourceRemote::Git {
 				git_url,
 				branch,
-			} => {
-				use common::anyhow::bail;
-				use std::process::Command;
-				println!("[INFO] Clone `{git_url}`");
-				let mut cmd = Command::new("git");
-				cmd.arg("clone")
-					// Only keep the last commit
-					.arg("--depth")
-					.arg("1")
-					.arg("--single-branch");
-				if let Some(branch) = branch {
-					cmd.arg("-b");
-					cmd.arg(branch);
-				}
-				let status = cmd.arg(git_url).arg(dest_path).status()?;
-				if !status.success() {
-					bail!("Cloning from `{git_url}` failed");
-				}
-			}
+				tag,
+				commit,
+			} => fetch_git(
+				git_url,
+				branch.as_deref(),
+				tag.as_deref(),
+				commit.as_deref(),
+				&dest_path,
+				log,
+			)?,
 			_ => {}
 		}
 		Ok(())
 	}
 }
 
+/// A patch applied to the sources of a package before it is built.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Patch {
+	/// The path to the patch file, relative to the directory containing the build descriptor
+	pub path: PathBuf,
+	/// The number of leading components to strip from the paths of the files in the patch
+	#[serde(default = "default_strip")]
+	pub strip: usize,
+	/// The directory, relative to the build directory, in which the patch is applied
+	#[serde(default)]
+	pub location: PathBuf,
+}
+
+fn default_strip() -> usize {
+	1
+}
+
+/// An optional build feature, enabling additional functionalities of the package at the cost of
+/// additional dependencies.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Feature {
+	/// The feature's description
+	#[serde(default)]
+	pub description: String,
+	/// Tells whether the feature is enabled unless specified otherwise
+	#[serde(default)]
+	pub default: bool,
+	/// Dependencies required by the feature, added to the package's dependencies when the feature
+	/// is enabled
+	#[serde(default, skip_serializing_if = "Vec::is_empty", rename = "dep")]
+	pub deps: Vec<Dependency>,
+}
+
+/// Network access granted to the build hook.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Network {
+	/// The reason why the package cannot be built without network access
+	pub reason: String,
+}
+
+/// Configuration files of a package, which are preserved when edited by users.
+///
+/// Paths are the absolute paths of the files on the installed system.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Config {
+	/// Paths to configuration files
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub files: Vec<PathBuf>,
+	/// Paths to directories in which every file is a configuration file
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub prefixes: Vec<PathBuf>,
+}
+
+impl Config {
+	/// Tells whether the file at `path`, relative to the system root, is a configuration file.
+	pub fn contains(&self, path: &Path) -> bool {
+		let relative = |p: &PathBuf| p.strip_prefix("/").unwrap_or(p).to_path_buf();
+		self.files.iter().any(|f| relative(f) == path)
+			|| self.prefixes.iter().any(|p| {
+				let p = relative(p);
+				path != p && path.starts_with(p)
+			})
+	}
+}
+
 /// Description of how to build a package.
 #[derive(Deserialize, Serialize)]
 pub struct BuildDescriptor {
@@ -149,4 +525,106 @@ pub struct BuildDescriptor {
 	/// The list of sources for the package
 	#[serde(default, skip_serializing_if = "Vec::is_empty")]
 	pub source: Vec<Source>,
+	/// Optional build features, by name
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub features: BTreeMap<String, Feature>,
+	/// Patches applied to the sources after they are fetched, in order
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub patch: Vec<Patch>,
+	/// If set, hooks have network access even when isolated from the host
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub network: Option<Network>,
+	/// Configuration files of the package
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub config: Option<Config>,
+}
+
+impl BuildDescriptor {
+	/// Enables build features, adding their dependencies to the package's.
+	///
+	/// Arguments:
+	/// - `names` is the list of features to enable
+	/// - `default` tells whether default features are enabled too
+	///
+	/// The function returns the sorted list of enabled features.
+	pub fn enable_features(&mut self, names: &[String], default: bool) -> Result<Vec<String>> {
+		if let Some(name) = names.iter().find(|n| !self.features.contains_key(*n)) {
+			bail!("unknown feature `{name}`");
+		}
+		let enabled: Vec<String> = self
+			.features
+			.iter()
+			.filter(|(name, feature)| (default && feature.default) || names.contains(name))
+			.map(|(name, _)| name.clone())
+			.collect();
+		for name in &enabled {
+			let deps = self.features[name].deps.iter().cloned();
+			self.package.deps.extend(deps);
+		}
+		Ok(enabled)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::util::create_tmp_file;
+	use std::{env, io::Write};
+
+	#[test]
+	fn checksums() {
+		let desc: BuildDescriptor = toml::from_str(
+			r#"
+			[package]
+			name = "hello"
+			version = "1.0"
+			description = ""
+
+			[[source]]
+			location = "/"
+			url = "https://example.org/hello-1.0.tar.gz"
+			sha256 = "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824"
+			"#,
+		)
+		.unwrap();
+		let SourceRemote::Url {
+			checksums, ..
+		} = desc.source[0].remote()
+		else {
+			panic!("invalid source type");
+		};
+		assert_eq!(
+			checksums.cache_key().unwrap(),
+			"sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+		);
+		let (path, mut file) = create_tmp_file(env::temp_dir()).unwrap();
+		file.write_all(b"hello").unwrap();
+		checksums.verify(&mut file).unwrap();
+		file.write_all(b"!").unwrap();
+		assert!(checksums.verify(&mut file).is_err());
+		fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn config() {
+		let desc: BuildDescriptor = toml::from_str(
+			r#"
+			[package]
+			name = "hello"
+			version = "1.0"
+			description = ""
+
+			[config]
+			files = ["/etc/hello.conf"]
+			prefixes = ["/etc/hello.d"]
+			"#,
+		)
+		.unwrap();
+		let config = desc.config.unwrap();
+		assert!(config.contains(Path::new("etc/hello.conf")));
+		assert!(config.contains(Path::new("etc/hello.d/default.conf")));
+		assert!(!config.contains(Path::new("etc/hello.d")));
+		assert!(!config.contains(Path::new("etc/hello.d.conf")));
+		assert!(!config.contains(Path::new("usr/bin/hello")));
+	}
 }
@@ -0,0 +1,220 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Build logs.
+//!
+//! Each build writes a log in the work directory, recording every phase of the build along with
+//! the output of the commands it runs and the environment of hooks. Each line is prefixed by the
+//! time at which it was written.
+//!
+//! The log is kept after the build, so that a failed build can be troubleshot afterwards.
+
+use crate::util::UtcTime;
+use std::{
+	ffi::OsStr,
+	fmt,
+	fs::{self, File},
+	io::{self, BufRead, BufReader, Read, Write},
+	path::{Path, PathBuf},
+	process::{Command, ExitStatus, Stdio},
+	sync::{Arc, Mutex},
+	thread::{self, JoinHandle},
+};
+
+/// The number of lines of the log printed when a build fails.
+pub const TAIL_LINES: usize = 30;
+
+/// Parts of the names of environment variables whose values are not written to logs, since they
+/// are likely to hold credentials.
+const SECRET_PATTERNS: &[&str] = &["SECRET", "TOKEN", "KEY", "PASSWORD"];
+
+/// Tells whether the environment variable `name` is likely to hold a secret.
+fn is_secret(name: &str) -> bool {
+	let name = name.to_uppercase();
+	SECRET_PATTERNS.iter().any(|p| name.contains(p))
+}
+
+/// Returns the path to the log of the build of the package `name` in `work_dir`.
+pub fn get_path(work_dir: &Path, name: &str) -> PathBuf {
+	work_dir.join("logs").join(format!("{name}.log"))
+}
+
+/// The log of a build.
+#[derive(Clone)]
+pub struct BuildLog {
+	/// The path to the log file.
+	path: PathBuf,
+	/// The log file.
+	file: Arc<Mutex<File>>,
+}
+
+impl BuildLog {
+	/// Creates the log for the package `name` in `work_dir`, replacing the log of any previous
+	/// build of the same package.
+	pub fn create(work_dir: &Path, name: &str) -> io::Result<Self> {
+		fs::create_dir_all(work_dir.join("logs"))?;
+		let path = get_path(&fs::canonicalize(work_dir)?, name);
+		let file = File::create(&path)?;
+		Ok(Self {
+			path,
+			file: Arc::new(Mutex::new(file)),
+		})
+	}
+
+	/// Returns the path to the log file.
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Writes `content` to the log, prefixing each line with a timestamp.
+	///
+	/// Logging is best-effort: errors are ignored so that they do not fail the build.
+	pub fn write(&self, content: impl AsRef<[u8]>) {
+		let t = UtcTime::now();
+		let timestamp = format!(
+			"[{:04}-{:02}-{:02} {:02}:{:02}:{:02}] ",
+			t.year, t.month, t.day, t.hour, t.minute, t.second
+		);
+		let mut file = self.file.lock().unwrap();
+		for line in content.as_ref().split_inclusive(|b| *b == b'\n') {
+			let _ = file.write_all(timestamp.as_bytes());
+			let _ = file.write_all(line);
+			if !line.ends_with(b"\n") {
+				let _ = file.write_all(b"\n");
+			}
+		}
+	}
+
+	/// Marks the beginning of the phase `name` in the log.
+	pub fn phase(&self, name: &str) {
+		self.write(format!("==> Phase `{name}`"));
+	}
+
+	/// Prints the information message `msg` and writes it to the log.
+	pub fn info(&self, msg: impl fmt::Display) {
+		let msg = format!("[INFO] {msg}");
+		println!("{msg}");
+		self.write(msg);
+	}
+
+	/// Writes the environment variables `vars` to the log.
+	///
+	/// The values of variables which are likely to hold secrets are redacted.
+	pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&self, vars: impl IntoIterator<Item = (K, V)>) {
+		for (name, value) in vars {
+			let name = name.as_ref().to_string_lossy();
+			if is_secret(&name) {
+				self.write(format!("env: {name}=<redacted>"));
+			} else {
+				self.write(format!("env: {name}={}", value.as_ref().to_string_lossy()));
+			}
+		}
+	}
+
+	/// Spawns a thread copying the content of `src` to `dst` and to the log, line by line.
+	fn tee<R, W>(&self, src: R, mut dst: W) -> JoinHandle<()>
+	where
+		R: Read + Send + 'static,
+		W: Write + Send + 'static,
+	{
+		let log = self.clone();
+		thread::spawn(move || {
+			let mut src = BufReader::new(src);
+			let mut line = vec![];
+			loop {
+				line.clear();
+				match src.read_until(b'\n', &mut line) {
+					Ok(0) | Err(_) => break,
+					Ok(_) => {}
+				}
+				let _ = dst.write_all(&line);
+				let _ = dst.flush();
+				log.write(&line);
+			}
+		})
+	}
+
+	/// Runs `cmd` until it exits, copying its output to both the terminal and the log.
+	pub fn run(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+		let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+		let stdout = child.stdout.take().map(|out| self.tee(out, io::stdout()));
+		let stderr = child.stderr.take().map(|err| self.tee(err, io::stderr()));
+		let status = child.wait()?;
+		for thread in stdout.into_iter().chain(stderr) {
+			let _ = thread.join();
+		}
+		self.write(format!("exit: {status}"));
+		Ok(status)
+	}
+
+	/// Runs `cmd` until it exits, copying its error output to both the terminal and the log.
+	///
+	/// The function returns the exit status of the command along with its standard output, which
+	/// is written to the log too.
+	pub fn output(&self, cmd: &mut Command) -> io::Result<(ExitStatus, Vec<u8>)> {
+		self.write(format!("run: {cmd:?}"));
+		let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+		let stderr = child.stderr.take().map(|err| self.tee(err, io::stderr()));
+		let mut stdout = vec![];
+		if let Some(mut out) = child.stdout.take() {
+			out.read_to_end(&mut stdout)?;
+		}
+		let status = child.wait()?;
+		if let Some(thread) = stderr {
+			let _ = thread.join();
+		}
+		self.write(&stdout);
+		self.write(format!("exit: {status}"));
+		Ok((status, stdout))
+	}
+
+	/// Returns the last `n` lines of the log.
+	pub fn tail(&self, n: usize) -> io::Result<Vec<String>> {
+		let content = fs::read(&self.path)?;
+		let content = String::from_utf8_lossy(&content);
+		let lines: Vec<&str> = content.lines().collect();
+		let start = lines.len().saturating_sub(n);
+		Ok(lines[start..].iter().map(|l| l.to_string()).collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::util::create_tmp_dir;
+	use std::env;
+
+	#[test]
+	fn redact_secrets() {
+		let work_dir = create_tmp_dir(env::temp_dir()).unwrap();
+		let log = BuildLog::create(&work_dir, "foo").unwrap();
+		log.env([
+			("PATH", "/usr/bin"),
+			("AWS_SECRET_ACCESS_KEY", "hunter2"),
+			("github_token", "hunter3"),
+			("DB_PASSWORD", "hunter4"),
+		]);
+		let content = fs::read_to_string(log.path()).unwrap();
+		fs::remove_dir_all(&work_dir).unwrap();
+
+		assert!(content.contains("env: PATH=/usr/bin\n"));
+		assert!(content.contains("env: AWS_SECRET_ACCESS_KEY=<redacted>\n"));
+		assert!(content.contains("env: github_token=<redacted>\n"));
+		assert!(!content.contains("hunter"));
+	}
+}
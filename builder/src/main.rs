@@ -18,24 +18,41 @@
 
 //! Utility allowing to build packages.
 
+mod archive;
+mod bootstrap;
 mod build;
+mod build_all;
 #[allow(unused)]
 mod cache;
+mod checksum;
 mod desc;
+mod log;
+mod publish;
+mod reproducible;
+mod sandbox;
 mod util;
 
 use crate::{
-	build::BuildProcess,
+	build::{BuildInputs, BuildProcess, Isolation},
+	publish::{
+		build_index,
+		local::Local,
+		prune::{self, Policy},
+		s3::S3,
+		snapshot,
+		ssh::Ssh,
+		upload_package, AnyBackend, Backend,
+	},
 	util::{get_build_triplet, get_jobs_count},
 };
 use clap::{Args, Parser, Subcommand};
 use common::{
-	anyhow::{self, anyhow, bail, Result},
-	repository::{Index, IndexArch, Repository},
+	anyhow::{anyhow, bail, Result},
+	package::ArchiveFormat,
+	repository::Repository,
 	tokio::runtime::Runtime,
 };
-use s3::{creds::Credentials, Region};
-use std::{fs, path::PathBuf, process::exit, str, str::FromStr};
+use std::{fs, path::PathBuf, process::exit};
 
 /// Build, store and index packages
 #[derive(Debug, Parser)]
@@ -49,10 +66,22 @@ struct Cli {
 enum Command {
 	/// Build a package
 	Build(BuildArgs),
-	/// Build the index of a s3 bucket repository
+	/// Build every package of a tree of build descriptors, in dependency order
+	BuildAll(BuildAllArgs),
+	/// Build a cross-compilation toolchain in a sysroot
+	Bootstrap(BootstrapArgs),
+	/// Compute the checksums of the sources of a package and write them to its descriptor
+	Checksum(ChecksumArgs),
+	/// Build a package twice and compare the resulting archives
+	VerifyReproducible(VerifyReproducibleArgs),
+	/// Build the index of a published repository
 	Index(IndexArgs),
-	/// Upload packages from a local repository to a s3 bucket
+	/// Upload packages from a local repository to a published repository
 	Upload(UploadArgs),
+	/// Remove packages from a published repository, then re-index it
+	Prune(PruneArgs),
+	/// Create an immutable snapshot of the index of a published repository
+	Snapshot(SnapshotArgs),
 }
 
 /// Build a package according to its descriptor
@@ -86,6 +115,27 @@ struct BuildArgs {
 	/// Build in a chroot environment
 	#[arg(long)]
 	chroot: bool,
+	/// Build in an unprivileged sandbox, based on user and mount namespaces
+	#[arg(long, conflicts_with = "chroot")]
+	sandbox: bool,
+	/// Enable the given build feature. May be specified several times
+	#[arg(long = "feature")]
+	features: Vec<String>,
+	/// Build even if the output repository holds a package built from the same inputs
+	#[arg(long)]
+	force: bool,
+	/// Compression format of the package's archive: `gzip`, `xz` or `zstd`
+	#[arg(long, default_value = "gzip")]
+	archive_format: ArchiveFormat,
+	/// Do not enable the features the package enables by default
+	#[arg(long)]
+	no_default_features: bool,
+	/// Run the package's test suite after building it, with check dependencies installed
+	#[arg(long)]
+	check: bool,
+	/// Package even if the test suite fails
+	#[arg(long, requires = "check")]
+	ignore_check: bool,
 
 	/// If set, build files are kept for troubleshooting purpose
 	#[arg(long)]
@@ -96,35 +146,205 @@ struct BuildArgs {
 	work_dir: PathBuf,
 }
 
-/// Upload a package to a s3 bucket repository
+/// Build every package whose descriptor is found under a directory
 #[derive(Args, Debug)]
-struct UploadArgs {
-	/// Path to the package file (`.tar.gz` or `.meta`); both files are uploaded
+struct BuildAllArgs {
+	/// Path to the directory under which build descriptors are searched recursively
 	#[arg(long)]
 	from: PathBuf,
-	/// Bucket name
+	/// Path to the output repository. Packages already present in it are not built again
 	#[arg(long)]
-	bucket: String,
-	/// Bucket region
+	to: PathBuf,
+	/// Maximum number of packages built at the same time. Defaults to the number of CPUs divided
+	/// by the number of jobs
+	#[arg(short, long)]
+	parallel: Option<usize>,
+
+	/// Specifies the recommended number of jobs to build each package
+	#[arg(short, long)]
+	jobs: Option<usize>,
+	/// Target triplet of the machine on which the packages are built
+	#[arg(long)]
+	build: Option<String>,
+	/// Target triplet of the machine for which the packages are built
+	#[arg(long)]
+	host: Option<String>,
+	/// Target triplet for which the packages build
+	#[arg(long)]
+	target: Option<String>,
+	/// Build in a chroot environment
+	#[arg(long)]
+	chroot: bool,
+	/// Build in an unprivileged sandbox, based on user and mount namespaces
+	#[arg(long, conflicts_with = "chroot")]
+	sandbox: bool,
+	/// Compression format of the packages' archives: `gzip`, `xz` or `zstd`
+	#[arg(long, default_value = "gzip")]
+	archive_format: ArchiveFormat,
+	/// Run the test suite of each package after building it
+	#[arg(long)]
+	check: bool,
+	/// Package even if a test suite fails
+	#[arg(long, requires = "check")]
+	ignore_check: bool,
+	/// Build packages even if they are up to date
+	#[arg(long)]
+	force: bool,
+
+	/// Path to the work directory, containing build directories
+	#[arg(long, default_value = "work/")]
+	work_dir: PathBuf,
+}
+
+/// Bootstrap a cross-compilation toolchain. If a previous run failed, bootstrapping resumes at the
+/// stage that failed
+#[derive(Args, Debug)]
+struct BootstrapArgs {
+	/// Target triplet of the machine for which the toolchain is bootstrapped
+	#[arg(long)]
+	host: String,
+	/// Path to the sysroot in which the toolchain is built
+	#[arg(long)]
+	sysroot: PathBuf,
+	/// Path to the directory containing the descriptors of the bootstrap packages
+	#[arg(long, default_value = "bootstrap/desc/")]
+	from: PathBuf,
+
+	/// Specifies the recommended number of jobs to build each package
+	#[arg(short, long)]
+	jobs: Option<usize>,
+	/// Target triplet of the machine on which the toolchain is built
+	#[arg(long)]
+	build: Option<String>,
+
+	/// If set, build files are kept for troubleshooting purpose
+	#[arg(long)]
+	debug: bool,
+
+	/// Path to the work directory, containing build directories
+	#[arg(long, default_value = "work/")]
+	work_dir: PathBuf,
+}
+
+/// Fill in the checksums of the tarballs among the sources of a package
+#[derive(Args, Debug)]
+struct ChecksumArgs {
+	/// Path to the directory containing the package's descriptor
+	#[arg(long)]
+	from: PathBuf,
+	/// Write SHA-512 checksums instead of SHA-256
+	#[arg(long)]
+	sha512: bool,
+}
+
+/// Build a package twice, then check that both builds produced the same package
+#[derive(Args, Debug)]
+struct VerifyReproducibleArgs {
+	/// Path to the directory containing the package to build
+	#[arg(long)]
+	from: PathBuf,
+
+	/// Specifies the recommended number of jobs to build the package
+	#[arg(short, long)]
+	jobs: Option<usize>,
+	/// Target triplet of the machine on which the package is built
+	#[arg(long)]
+	build: Option<String>,
+	/// Target triplet of the machine for which the package is built
+	#[arg(long)]
+	host: Option<String>,
+	/// Target triplet for which the package builds
 	#[arg(long)]
-	region: String,
-	/// Bucket endpoint
+	target: Option<String>,
+	/// Build in a chroot environment
+	#[arg(long)]
+	chroot: bool,
+	/// Build in an unprivileged sandbox, based on user and mount namespaces
+	#[arg(long, conflicts_with = "chroot")]
+	sandbox: bool,
+	/// Enable the given build feature. May be specified several times
+	#[arg(long = "feature")]
+	features: Vec<String>,
+	/// Do not enable the features the package enables by default
 	#[arg(long)]
+	no_default_features: bool,
+
+	/// Compression format of the package's archive: `gzip`, `xz` or `zstd`
+	#[arg(long, default_value = "gzip")]
+	archive_format: ArchiveFormat,
+
+	/// Path to the work directory, containing build directories and the output of both builds
+	#[arg(long, default_value = "work/")]
+	work_dir: PathBuf,
+}
+
+/// Location of a published repository. Exactly one of `--bucket`, `--dir` or `--ssh` must be
+/// specified
+#[derive(Args, Debug)]
+struct PublishArgs {
+	/// Name of the S3 bucket holding the repository
+	#[arg(long)]
+	bucket: Option<String>,
+	/// Bucket region
+	#[arg(long, requires = "bucket")]
+	region: Option<String>,
+	/// Bucket endpoint, for S3-compatible storages
+	#[arg(long, requires = "bucket")]
 	endpoint: Option<String>,
+	/// Path to a local directory holding the repository
+	#[arg(long, conflicts_with = "bucket")]
+	dir: Option<PathBuf>,
+	/// Directory on a remote host holding the repository, accessed through SSH, in the form
+	/// `[user@]host:path`
+	#[arg(long, conflicts_with_all = ["bucket", "dir"])]
+	ssh: Option<String>,
 }
 
-/// Index a s3 bucket repository
+/// Upload a package to a repository
+#[derive(Args, Debug)]
+struct UploadArgs {
+	/// Path to the package file (`.tar.gz` or `.meta`); both files are uploaded
+	#[arg(long)]
+	from: PathBuf,
+	#[command(flatten)]
+	repo: PublishArgs,
+}
+
+/// Index a repository
 #[derive(Args, Debug)]
 struct IndexArgs {
-	/// Bucket name
+	#[command(flatten)]
+	repo: PublishArgs,
+}
+
+/// Remove packages from a repository according to retention policies
+#[derive(Args, Debug)]
+struct PruneArgs {
+	/// Keep only the latest N versions of each package, for each architecture
 	#[arg(long)]
-	bucket: String,
-	/// Bucket region
+	keep_latest: Option<usize>,
+	/// Keep the packages referenced by the snapshot with the given ID. May be specified several
+	/// times
+	#[arg(long = "pin")]
+	pins: Vec<String>,
+	/// Delete archives which have no metadata
 	#[arg(long)]
-	region: String,
-	/// Bucket endpoint
+	orphans: bool,
+	/// Print the files that would be deleted without modifying the repository
 	#[arg(long)]
-	endpoint: Option<String>,
+	dry_run: bool,
+	#[command(flatten)]
+	repo: PublishArgs,
+}
+
+/// Create a snapshot of a repository's index
+#[derive(Args, Debug)]
+struct SnapshotArgs {
+	/// ID of the snapshot. Defaults to the current date and time
+	#[arg(long)]
+	id: Option<String>,
+	#[command(flatten)]
+	repo: PublishArgs,
 }
 
 /// Returns the architecture directory name for the given `host`
@@ -139,8 +359,8 @@ fn get_arch(host: &str) -> &str {
 
 fn build(args: BuildArgs) -> Result<()> {
 	// Read environment
-	let jobs = get_jobs_count(&args);
-	let build = get_build_triplet(&args)?;
+	let jobs = get_jobs_count(args.jobs);
+	let build = get_build_triplet(args.build.as_deref())?;
 	let host = args.host.as_deref().unwrap_or(build.as_str());
 	let arch = get_arch(host);
 	let target = args.target.as_deref().unwrap_or(host);
@@ -148,41 +368,107 @@ fn build(args: BuildArgs) -> Result<()> {
 		.map_err(|e| anyhow!("failed to create destination directory: {e}"))?;
 	println!("[INFO] Jobs: {jobs}; Build: {build}; Host: {host}; Target: {target}");
 	let pkg_path = (!args.package).then(|| args.to.clone());
+	let isolation = if args.chroot {
+		Isolation::Chroot
+	} else if args.sandbox {
+		Isolation::Sandbox
+	} else {
+		Isolation::None
+	};
 	let rt = Runtime::new()?;
-	let build_process = rt
-		.block_on(async {
-			let build_process =
-				BuildProcess::new(args.from, pkg_path, &args.work_dir, args.chroot).await?;
-			if args.debug {
-				eprintln!(
-					"[DEBUG] Build directory path: {}; Install path: {}; Sysroot: {}",
-					build_process.build_dir.display(),
-					build_process.install_path.display(),
-					build_process.sysroot.display()
-				);
+	let mut inputs = rt
+		.block_on(BuildInputs::read(
+			args.from,
+			pkg_path.is_some(),
+			&args.features,
+			!args.no_default_features,
+			args.check,
+			isolation,
+		))
+		.map_err(|e| anyhow!("cannot prepare build: {e}"))?;
+	let repo = Repository::local(args.to.clone());
+	// Check whether the build can be skipped before creating its directories
+	if args.package {
+		inputs.set_archive_format(args.archive_format);
+		match inputs.input_hash(&build, host, target)? {
+			Some(hash) => {
+				if !args.force && inputs.is_built(&repo, arch, &hash)? {
+					println!("[INFO] Package is up to date (input hash: {hash}), skipping build");
+					return Ok(());
+				}
+				inputs.set_input_hash(hash);
 			}
-			build_process.fetch_sources().await?;
-			Ok::<_, anyhow::Error>(build_process)
-		})
-		.map_err(|e| anyhow!("cannot fetch sources: {e}"))?;
-	println!("[INFO] Compilation...");
-	let success = build_process
-		.build(jobs, &build, host, target)
-		.map_err(|e| anyhow!("cannot build package: {e}"))?;
-	if !success {
-		bail!("package build failed");
+			None => println!("[INFO] Sources are not pinned, the build cannot be skipped"),
+		}
 	}
-	if args.package {
-		println!("[INFO] Prepare repository at `{}`...", args.to.display());
-		let repo = Repository::local(args.to.clone());
-		build_process
-			.write_metadata(&repo, arch)
-			.map_err(|e| anyhow!("failed to write package metadata: {e}"))?;
-		println!("[INFO] Create archive...");
-		build_process
-			.create_archive(&repo, arch)
-			.map_err(|e| anyhow!("failed to create package archive: {e}"))?;
+	let mut build_process = BuildProcess::new(inputs, pkg_path, &args.work_dir, isolation)
+		.map_err(|e| anyhow!("cannot prepare build: {e}"))?;
+	if args.debug {
+		eprintln!(
+			"[DEBUG] Build directory path: {}; Install path: {}; Sysroot: {}",
+			build_process.build_dir.display(),
+			build_process.install_path.display(),
+			build_process.sysroot.display()
+		);
 	}
+	let log = build_process.log().clone();
+	let res = (|| {
+		log.phase("sysroot");
+		rt.block_on(build_process.prepare_sysroot())
+			.map_err(|e| anyhow!("cannot prepare sysroot: {e}"))?;
+		log.phase("fetch");
+		rt.block_on(build_process.fetch_sources())
+			.map_err(|e| anyhow!("cannot fetch sources: {e}"))?;
+		log.phase("patch");
+		build_process.apply_patches()?;
+		log.phase("build");
+		log.info("Compilation...");
+		let success = build_process
+			.build(jobs, &build, host, target)
+			.map_err(|e| anyhow!("cannot build package: {e}"))?;
+		if !success {
+			bail!("package build failed");
+		}
+		if args.check {
+			log.phase("check");
+			log.info("Checks...");
+			let success = build_process
+				.check(jobs, &build, host, target)
+				.map_err(|e| anyhow!("cannot check package: {e}"))?;
+			if !success {
+				if !args.ignore_check {
+					bail!("package checks failed");
+				}
+				eprintln!("[WARN] Package checks failed, ignored");
+				log.write("Package checks failed, ignored");
+			}
+		}
+		if args.package {
+			log.phase("package");
+			log.info(format_args!(
+				"Prepare repository at `{}`...",
+				args.to.display()
+			));
+			build_process
+				.write_metadata(&repo, arch)
+				.map_err(|e| anyhow!("failed to write package metadata: {e}"))?;
+			log.info("Create archive...");
+			let epoch = archive::source_date_epoch()?;
+			build_process
+				.create_archive(&repo, arch, epoch)
+				.map_err(|e| anyhow!("failed to create package archive: {e}"))?;
+		}
+		Ok(())
+	})();
+	if let Err(e) = &res {
+		log.write(format!("error: {e}"));
+		eprintln!("[ERROR] Last lines of the build log:");
+		for line in log.tail(log::TAIL_LINES)? {
+			eprintln!("{line}");
+		}
+	}
+	println!("[INFO] Build log: {}", log.path().display());
+	res?;
 	if !args.debug {
 		println!("[INFO] Cleaning up...");
 		build_process.cleanup(args.package)?;
@@ -190,97 +476,76 @@ fn build(args: BuildArgs) -> Result<()> {
 	Ok(())
 }
 
-async fn upload(args: UploadArgs) -> Result<()> {
-	let region = match args.endpoint {
-		Some(endpoint) => Region::Custom {
-			region: args.region,
-			endpoint,
-		},
-		None => Region::from_str(&args.region)?,
-	};
-	let credentials = Credentials::default()?;
-	let bucket = s3::Bucket::new(&args.bucket, region, credentials)?;
-	// Derive the stem (strip .tar.gz or .meta extension)
-	let path = args.from.canonicalize()?;
-	let dir = path
-		.parent()
-		.ok_or_else(|| anyhow!("path has no parent directory"))?;
-	let arch = dir
-		.file_name()
-		.and_then(|n| n.to_str())
-		.ok_or_else(|| anyhow!("cannot determine architecture from parent directory name"))?;
-	let filename = path
-		.file_name()
-		.and_then(|n| n.to_str())
-		.ok_or_else(|| anyhow!("invalid filename"))?;
-	let stem = filename
-		.strip_suffix(".tar.gz")
-		.or_else(|| filename.strip_suffix(".meta"))
-		.ok_or_else(|| anyhow!("file must end with `.tar.gz` or `.meta`"))?;
-	for ext in [".tar.gz", ".meta"] {
-		let file_path = dir.join(format!("{stem}{ext}"));
-		let key = format!("dist/{arch}/{stem}{ext}");
-		println!("Upload `{key}`...");
-		let data = fs::read(&file_path)
-			.map_err(|e| anyhow!("failed to read `{}`: {e}", file_path.display()))?;
-		bucket.put_object(&key, &data).await?;
+/// Opens the backend of the repository described by `args`.
+fn open_backend(args: PublishArgs) -> Result<AnyBackend> {
+	match (args.bucket, args.dir, args.ssh) {
+		(Some(bucket), None, None) => {
+			let region = args
+				.region
+				.ok_or_else(|| anyhow!("`--bucket` requires `--region`"))?;
+			Ok(AnyBackend::S3(S3::new(&bucket, region, args.endpoint)?))
+		}
+		(None, Some(dir), None) => Ok(AnyBackend::Local(Local::new(dir)?)),
+		(None, None, Some(remote)) => Ok(AnyBackend::Ssh(Ssh::new(&remote)?)),
+		_ => bail!("exactly one of `--bucket`, `--dir` or `--ssh` must be specified"),
 	}
+}
+
+async fn upload(args: UploadArgs) -> Result<()> {
+	let backend = open_backend(args.repo)?;
+	upload_package(&backend, &args.from).await?;
 	println!("Done!");
 	Ok(())
 }
 
 async fn index(args: IndexArgs) -> Result<()> {
-	let region = match args.endpoint {
-		Some(endpoint) => Region::Custom {
-			region: args.region,
-			endpoint,
-		},
-		None => Region::from_str(&args.region)?,
-	};
-	let credentials = Credentials::default()?;
-	let bucket = s3::Bucket::new(&args.bucket, region, credentials)?;
-	let entries = bucket.list("dist/".to_owned(), None).await?;
-	let iter = entries.into_iter().flat_map(|n| n.contents).flat_map(|e| {
-		let key = e.key.strip_prefix("dist/")?;
-		let separator_off = key.find('/')?;
-		let (arch, _) = key.split_at(separator_off);
-		if e.key.ends_with(".meta") {
-			Some((arch.to_owned(), e.key))
-		} else {
-			None
-		}
-	});
-	let mut index = Index::default();
-	for (arch, key) in iter {
-		println!("Download `{key}`...");
-		let data = bucket.get_object(&key).await?.to_vec();
-		let Ok(data) = str::from_utf8(&data) else {
-			eprintln!("warning: `{key}` has invalid UTF8, ignored");
-			continue;
-		};
-		let pkg = match toml::from_str(data) {
-			Ok(p) => p,
-			Err(e) => {
-				eprintln!("warning: `{key}` is invalid, ignored: {e}");
-				continue;
-			}
-		};
-		let ent = index.arch.entry(arch).or_insert(IndexArch::default());
-		ent.package.push(pkg);
-	}
+	let backend = open_backend(args.repo)?;
+	let index = build_index(&backend).await?;
 	if index.arch.is_empty() {
 		eprintln!("warning: no package found");
 	}
 	println!("Upload index...");
-	let index = toml::to_string(&index).unwrap();
-	bucket.put_object("/index", index.as_bytes()).await?;
+	backend.put_index(&index).await?;
 	println!("Done!");
 	Ok(())
 }
 
+async fn prune(args: PruneArgs) -> Result<()> {
+	let backend = open_backend(args.repo)?;
+	let policy = Policy {
+		keep_latest: args.keep_latest,
+		pins: args.pins,
+		orphans: args.orphans,
+	};
+	prune::prune(&backend, &policy, args.dry_run).await?;
+	println!("Done!");
+	Ok(())
+}
+
+async fn snapshot(args: SnapshotArgs) -> Result<()> {
+	let backend = open_backend(args.repo)?;
+	let id = args.id.unwrap_or_else(snapshot::default_id);
+	snapshot::create(&backend, &id).await?;
+	println!("Created snapshot `{id}`");
+	Ok(())
+}
+
 fn main_impl(cmd: Command) -> Result<()> {
 	match cmd {
 		Command::Build(a) => build(a),
+		Command::Bootstrap(a) => {
+			let build = get_build_triplet(a.build.as_deref())?;
+			bootstrap::bootstrap(a, build)
+		}
+		Command::BuildAll(a) => {
+			let build = get_build_triplet(a.build.as_deref())?;
+			build_all::build_all(&a, &build)
+		}
+		Command::VerifyReproducible(a) => reproducible::verify(a),
+		Command::Checksum(a) => {
+			let rt = Runtime::new()?;
+			rt.block_on(checksum::fill(&a.from, a.sha512))
+		}
 		Command::Index(a) => {
 			let rt = Runtime::new()?;
 			rt.block_on(index(a))
@@ -289,6 +554,14 @@ fn main_impl(cmd: Command) -> Result<()> {
 			let rt = Runtime::new()?;
 			rt.block_on(upload(a))
 		}
+		Command::Prune(a) => {
+			let rt = Runtime::new()?;
+			rt.block_on(prune(a))
+		}
+		Command::Snapshot(a) => {
+			let rt = Runtime::new()?;
+			rt.block_on(snapshot(a))
+		}
 	}
 }
 
@@ -0,0 +1,143 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Publishing to a local directory.
+//!
+//! The directory can then be served by any HTTP server, or be used as a staging area before
+//! publishing to another backend.
+
+use super::Backend;
+use common::anyhow::{anyhow, Result};
+use std::{
+	fs, io,
+	path::{Path, PathBuf},
+};
+
+/// Appends the keys of all files under `dir` to `keys`, recursively.
+///
+/// `key` is the key corresponding to `dir`.
+fn list_dir(dir: &Path, key: &str, keys: &mut Vec<String>) -> io::Result<()> {
+	let entries = match fs::read_dir(dir) {
+		Ok(e) => e,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+		Err(e) => return Err(e),
+	};
+	for ent in entries {
+		let ent = ent?;
+		let Ok(name) = ent.file_name().into_string() else {
+			continue;
+		};
+		let ent_key = if key.is_empty() {
+			name
+		} else {
+			format!("{key}/{name}")
+		};
+		if ent.file_type()?.is_dir() {
+			list_dir(&ent.path(), &ent_key, keys)?;
+		} else {
+			keys.push(ent_key);
+		}
+	}
+	Ok(())
+}
+
+/// A local directory.
+pub struct Local {
+	/// The path to the root of the repository.
+	path: PathBuf,
+}
+
+impl Local {
+	/// Opens the directory at `path`, creating it if it does not exist.
+	pub fn new(path: PathBuf) -> Result<Self> {
+		fs::create_dir_all(&path)
+			.map_err(|e| anyhow!("failed to create `{}`: {e}", path.display()))?;
+		Ok(Self {
+			path,
+		})
+	}
+}
+
+impl Backend for Local {
+	async fn upload(&self, key: &str, data: &[u8]) -> Result<()> {
+		let path = self.path.join(key);
+		if let Some(parent) = path.parent() {
+			fs::create_dir_all(parent)?;
+		}
+		// Write to a temporary file first so that clients never see a partially written file
+		let tmp_path = self.path.join(format!("{key}.tmp"));
+		fs::write(&tmp_path, data)?;
+		fs::rename(tmp_path, path)?;
+		Ok(())
+	}
+
+	async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+		let mut keys = vec![];
+		list_dir(&self.path, "", &mut keys)?;
+		keys.retain(|k| k.starts_with(prefix));
+		Ok(keys)
+	}
+
+	async fn get(&self, key: &str) -> Result<Vec<u8>> {
+		let path = self.path.join(key);
+		fs::read(&path).map_err(|e| anyhow!("failed to read `{}`: {e}", path.display()))
+	}
+
+	async fn delete(&self, key: &str) -> Result<()> {
+		match fs::remove_file(self.path.join(key)) {
+			Ok(_) => Ok(()),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+			Err(e) => Err(e.into()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::{repository::Index, tokio::runtime::Runtime, util::create_tmp_dir};
+	use std::env;
+
+	#[test]
+	fn round_trip() {
+		let path = create_tmp_dir(env::temp_dir()).unwrap();
+		let backend = Local::new(path.clone()).unwrap();
+		let rt = Runtime::new().unwrap();
+		rt.block_on(async {
+			backend
+				.upload("dist/x86_64/a_1.0.meta", b"a")
+				.await
+				.unwrap();
+			backend.upload("dist/x86/b_1.0.meta", b"b").await.unwrap();
+			backend.put_index(&Index::default()).await.unwrap();
+			let mut keys = backend.list("dist/").await.unwrap();
+			keys.sort_unstable();
+			assert_eq!(keys, ["dist/x86/b_1.0.meta", "dist/x86_64/a_1.0.meta"]);
+			assert_eq!(backend.get("dist/x86/b_1.0.meta").await.unwrap(), b"b");
+			assert!(backend.get("index").await.is_ok());
+			assert!(backend.get("missing").await.is_err());
+			backend.delete("dist/x86/b_1.0.meta").await.unwrap();
+			backend.delete("dist/x86/b_1.0.meta").await.unwrap();
+			assert_eq!(
+				backend.list("dist/").await.unwrap(),
+				["dist/x86_64/a_1.0.meta"]
+			);
+		});
+		fs::remove_dir_all(path).unwrap();
+	}
+}
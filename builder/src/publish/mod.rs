@@ -0,0 +1,215 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Publishing packages to a repository served to clients.
+//!
+//! A published repository follows the server-side files hierarchy described in `blimp(7)`. The
+//! storage holding it is abstracted by the [`Backend`] trait, so that the same repository can be
+//! published to a S3 bucket, a local directory or a directory on a remote host.
+
+pub mod local;
+pub mod prune;
+pub mod s3;
+pub mod snapshot;
+pub mod ssh;
+
+use common::{
+	anyhow::{anyhow, Result},
+	package::{ArchiveFormat, Package},
+	repository::{Index, IndexArch},
+};
+use std::{fs, path::Path, str};
+
+/// The key of the index in a published repository.
+pub const INDEX_KEY: &str = "index";
+
+/// Returns the key of the index of the snapshot with the given `id`.
+pub fn snapshot_index_key(id: &str) -> String {
+	format!("snapshots/{id}/index")
+}
+
+/// Returns the key of the archive corresponding to the metadata file at `meta_key`, compressed
+/// with `format`.
+pub fn archive_key(meta_key: &str, format: ArchiveFormat) -> String {
+	let stem = meta_key.strip_suffix(".meta").unwrap_or(meta_key);
+	format!("{stem}.{}", format.extension())
+}
+
+/// A storage on which a repository can be published.
+///
+/// Keys are paths relative to the root of the repository, using `/` as a separator.
+pub trait Backend {
+	/// Uploads `data` to the file at `key`, replacing it if it already exists.
+	async fn upload(&self, key: &str, data: &[u8]) -> Result<()>;
+
+	/// Returns the keys of all the files whose key starts with `prefix`.
+	async fn list(&self, prefix: &str) -> Result<Vec<String>>;
+
+	/// Returns the content of the file at `key`.
+	async fn get(&self, key: &str) -> Result<Vec<u8>>;
+
+	/// Deletes the file at `key`. If the file does not exist, the function does nothing.
+	async fn delete(&self, key: &str) -> Result<()>;
+
+	/// Publishes the given index, replacing the previous one.
+	async fn put_index(&self, index: &Index) -> Result<()> {
+		let index = toml::to_string(index)?;
+		self.upload(INDEX_KEY, index.as_bytes()).await
+	}
+}
+
+/// Backend selected at runtime.
+pub enum AnyBackend {
+	S3(s3::S3),
+	Local(local::Local),
+	Ssh(ssh::Ssh),
+}
+
+impl Backend for AnyBackend {
+	async fn upload(&self, key: &str, data: &[u8]) -> Result<()> {
+		match self {
+			Self::S3(b) => b.upload(key, data).await,
+			Self::Local(b) => b.upload(key, data).await,
+			Self::Ssh(b) => b.upload(key, data).await,
+		}
+	}
+
+	async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+		match self {
+			Self::S3(b) => b.list(prefix).await,
+			Self::Local(b) => b.list(prefix).await,
+			Self::Ssh(b) => b.list(prefix).await,
+		}
+	}
+
+	async fn get(&self, key: &str) -> Result<Vec<u8>> {
+		match self {
+			Self::S3(b) => b.get(key).await,
+			Self::Local(b) => b.get(key).await,
+			Self::Ssh(b) => b.get(key).await,
+		}
+	}
+
+	async fn delete(&self, key: &str) -> Result<()> {
+		match self {
+			Self::S3(b) => b.delete(key).await,
+			Self::Local(b) => b.delete(key).await,
+			Self::Ssh(b) => b.delete(key).await,
+		}
+	}
+}
+
+/// A package published in a repository.
+pub struct Published {
+	/// The architecture the package is built for.
+	pub arch: String,
+	/// The key of the package's metadata file.
+	pub meta_key: String,
+	/// The package's metadata.
+	pub package: Package,
+}
+
+/// Uploads a package from a local repository to `backend`.
+///
+/// `path` is the path to either the archive or the metadata file of the package. Both files are
+/// uploaded.
+pub async fn upload_package<B: Backend>(backend: &B, path: &Path) -> Result<()> {
+	// Derive the stem (strip the archive or .meta extension)
+	let path = path.canonicalize()?;
+	let dir = path
+		.parent()
+		.ok_or_else(|| anyhow!("path has no parent directory"))?;
+	let arch = dir
+		.file_name()
+		.and_then(|n| n.to_str())
+		.ok_or_else(|| anyhow!("cannot determine architecture from parent directory name"))?;
+	let filename = path
+		.file_name()
+		.and_then(|n| n.to_str())
+		.ok_or_else(|| anyhow!("invalid filename"))?;
+	let stem = ArchiveFormat::strip_extension(filename)
+		.map(|(stem, _)| stem)
+		.or_else(|| filename.strip_suffix(".meta"))
+		.ok_or_else(|| anyhow!("file must be a package archive or end with `.meta`"))?;
+	// The archive's format is given by the metadata
+	let meta_path = dir.join(format!("{stem}.meta"));
+	let package = Package::from_file(&meta_path)?
+		.ok_or_else(|| anyhow!("`{}` not found", meta_path.display()))?;
+	let archive_ext = package.archive_format.extension();
+	for ext in [archive_ext, "meta"] {
+		let file_path = dir.join(format!("{stem}.{ext}"));
+		let key = format!("dist/{arch}/{stem}.{ext}");
+		println!("Upload `{key}`...");
+		let data = fs::read(&file_path)
+			.map_err(|e| anyhow!("failed to read `{}`: {e}", file_path.display()))?;
+		backend.upload(&key, &data).await?;
+	}
+	Ok(())
+}
+
+/// Returns the list of packages published on `backend`, read from their metadata files.
+///
+/// Invalid metadata files are ignored with a warning.
+pub async fn list_packages<B: Backend>(backend: &B) -> Result<Vec<Published>> {
+	let keys = backend.list("dist/").await?;
+	let iter = keys.into_iter().filter_map(|key| {
+		let (arch, _) = key.strip_prefix("dist/")?.split_once('/')?;
+		let arch = arch.to_owned();
+		key.ends_with(".meta").then_some((arch, key))
+	});
+	let mut packages = vec![];
+	for (arch, meta_key) in iter {
+		println!("Download `{meta_key}`...");
+		let data = backend.get(&meta_key).await?;
+		let Ok(data) = str::from_utf8(&data) else {
+			eprintln!("warning: `{meta_key}` has invalid UTF8, ignored");
+			continue;
+		};
+		let package = match Package::parse(data) {
+			Ok(p) => p,
+			Err(e) => {
+				eprintln!("warning: `{meta_key}` is invalid, ignored: {e}");
+				continue;
+			}
+		};
+		packages.push(Published {
+			arch,
+			meta_key,
+			package,
+		});
+	}
+	Ok(packages)
+}
+
+/// Creates the index listing the given `packages`.
+pub fn make_index(packages: impl IntoIterator<Item = Published>) -> Index {
+	let mut index = Index::default();
+	for p in packages {
+		let ent = index.arch.entry(p.arch).or_insert(IndexArch::default());
+		ent.package.push(p.package);
+	}
+	index
+}
+
+/// Builds the index of the repository published on `backend`, from the metadata of all the
+/// packages it contains.
+///
+/// Invalid metadata files are ignored with a warning.
+pub async fn build_index<B: Backend>(backend: &B) -> Result<Index> {
+	Ok(make_index(list_packages(backend).await?))
+}
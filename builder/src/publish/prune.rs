@@ -0,0 +1,249 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Removal of packages from a published repository according to retention policies.
+
+use super::{archive_key, list_packages, make_index, snapshot_index_key, Backend, Published};
+use common::{
+	anyhow::{anyhow, Result},
+	package::ArchiveFormat,
+	repository::Index,
+	version::Version,
+};
+use std::{
+	collections::{HashMap, HashSet},
+	str,
+};
+
+/// Retention policies applied when pruning a repository.
+#[derive(Default)]
+pub struct Policy {
+	/// If set, only the latest N versions of each package are kept, for each architecture.
+	pub keep_latest: Option<usize>,
+	/// IDs of the snapshots whose packages must be kept.
+	pub pins: Vec<String>,
+	/// If `true`, archives without a metadata file are deleted.
+	pub orphans: bool,
+}
+
+/// Identifier of a package in a repository: architecture, name and version.
+type PackageId = (String, String, Version);
+
+/// Returns the packages referenced by the snapshot with the given `id`.
+async fn read_snapshot<B: Backend>(backend: &B, id: &str) -> Result<HashSet<PackageId>> {
+	let key = snapshot_index_key(id);
+	let data = backend
+		.get(&key)
+		.await
+		.map_err(|e| anyhow!("cannot read snapshot `{id}`: {e}"))?;
+	let data = str::from_utf8(&data).map_err(|_| anyhow!("`{key}` has invalid UTF8"))?;
+	let index: Index = toml::from_str(data)?;
+	let ids = index
+		.arch
+		.into_iter()
+		.flat_map(|(arch, a)| {
+			a.package
+				.into_iter()
+				.map(move |p| (arch.clone(), p.name, p.version))
+		})
+		.collect();
+	Ok(ids)
+}
+
+/// Returns the IDs of the snapshots published on `backend`.
+async fn list_snapshots<B: Backend>(backend: &B) -> Result<Vec<String>> {
+	let keys = backend.list("snapshots/").await?;
+	let ids = keys
+		.iter()
+		.filter_map(|key| key.strip_prefix("snapshots/")?.strip_suffix("/index"))
+		.map(str::to_owned)
+		.collect();
+	Ok(ids)
+}
+
+/// Splits `packages` into the list of packages to keep and the list of packages to delete.
+///
+/// Arguments:
+/// - `keep_latest` is the number of versions to keep for each package on each architecture. If
+///   `None`, all packages are kept
+/// - `pinned` is the set of packages that must be kept regardless of `keep_latest`
+fn select(
+	packages: Vec<Published>,
+	keep_latest: Option<usize>,
+	pinned: &HashSet<PackageId>,
+) -> (Vec<Published>, Vec<Published>) {
+	let Some(keep_latest) = keep_latest else {
+		return (packages, vec![]);
+	};
+	// Group versions of the same package
+	let mut groups: HashMap<(String, String), Vec<Published>> = HashMap::new();
+	for p in packages {
+		groups
+			.entry((p.arch.clone(), p.package.name.clone()))
+			.or_default()
+			.push(p);
+	}
+	let mut kept = vec![];
+	let mut deleted = vec![];
+	for (_, mut versions) in groups {
+		// Latest versions first
+		versions.sort_unstable_by(|p0, p1| p1.package.version.cmp(&p0.package.version));
+		for (i, p) in versions.into_iter().enumerate() {
+			let id = (
+				p.arch.clone(),
+				p.package.name.clone(),
+				p.package.version.clone(),
+			);
+			if i < keep_latest || pinned.contains(&id) {
+				kept.push(p);
+			} else {
+				deleted.push(p);
+			}
+		}
+	}
+	(kept, deleted)
+}
+
+/// Prunes the repository published on `backend` according to `policy`, then publishes the index
+/// of the remaining packages.
+///
+/// The new index is uploaded before files are deleted, so that it never references a deleted
+/// file, even if pruning is interrupted.
+///
+/// Packages referenced by snapshots that are not pinned may be deleted, in which case a warning
+/// is printed, since these snapshots become unusable.
+///
+/// If `dry_run` is `true`, the function only prints the files that would be deleted, without
+/// modifying the repository.
+pub async fn prune<B: Backend>(backend: &B, policy: &Policy, dry_run: bool) -> Result<()> {
+	let mut pinned = HashSet::new();
+	for id in &policy.pins {
+		pinned.extend(read_snapshot(backend, id).await?);
+	}
+	let packages = list_packages(backend).await?;
+	let meta_keys: HashSet<String> = packages.iter().map(|p| p.meta_key.clone()).collect();
+	let (kept, deleted) = select(packages, policy.keep_latest, &pinned);
+	for id in list_snapshots(backend).await? {
+		if policy.pins.contains(&id) {
+			continue;
+		}
+		let referenced = read_snapshot(backend, &id).await?;
+		for p in &deleted {
+			let pkg_id = (
+				p.arch.clone(),
+				p.package.name.clone(),
+				p.package.version.clone(),
+			);
+			if referenced.contains(&pkg_id) {
+				eprintln!(
+					"warning: `{}` is referenced by snapshot `{id}`, which will not be usable anymore",
+					p.meta_key
+				);
+			}
+		}
+	}
+	let mut keys: Vec<String> = deleted
+		.iter()
+		.flat_map(|p| {
+			let archive_key = archive_key(&p.meta_key, p.package.archive_format);
+			[archive_key, p.meta_key.clone()]
+		})
+		.collect();
+	if policy.orphans {
+		let orphans = backend.list("dist/").await?.into_iter().filter(|k| {
+			ArchiveFormat::strip_extension(k)
+				.is_some_and(|(stem, _)| !meta_keys.contains(&format!("{stem}.meta")))
+		});
+		keys.extend(orphans);
+	}
+	keys.sort_unstable();
+	println!(
+		"{} file(s) to delete, {} package(s) kept",
+		keys.len(),
+		kept.len()
+	);
+	if dry_run {
+		for key in &keys {
+			println!("Would delete `{key}`");
+		}
+		return Ok(());
+	}
+	let index = make_index(kept);
+	println!("Upload index...");
+	backend.put_index(&index).await?;
+	for key in &keys {
+		println!("Delete `{key}`...");
+		backend.delete(key).await?;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::package::Package;
+
+	fn published(arch: &str, name: &str, version: &str) -> Published {
+		Published {
+			arch: arch.to_owned(),
+			meta_key: format!("dist/{arch}/{name}_{version}.meta"),
+			package: Package {
+				name: name.to_owned(),
+				version: Version::try_from(version).unwrap(),
+				description: String::new(),
+				deps: vec![],
+				conflicts: vec![],
+				replaces: vec![],
+				provides: vec![],
+				patches: vec![],
+				input_hash: None,
+				archive_format: ArchiveFormat::Gzip,
+			},
+		}
+	}
+
+	#[test]
+	fn select_latest() {
+		let packages = vec![
+			published("x86_64", "foo", "1.0"),
+			published("x86_64", "foo", "1.2"),
+			published("x86_64", "foo", "1.10"),
+			published("x86", "foo", "1.0"),
+			published("x86_64", "bar", "2.0"),
+		];
+		let pinned = HashSet::from([(
+			"x86_64".to_owned(),
+			"foo".to_owned(),
+			Version::try_from("1.0").unwrap(),
+		)]);
+		let (kept, deleted) = select(packages, Some(1), &pinned);
+		let mut kept: Vec<_> = kept.into_iter().map(|p| p.meta_key).collect();
+		kept.sort_unstable();
+		assert_eq!(
+			kept,
+			[
+				"dist/x86/foo_1.0.meta",
+				"dist/x86_64/bar_2.0.meta",
+				"dist/x86_64/foo_1.0.meta",
+				"dist/x86_64/foo_1.10.meta",
+			]
+		);
+		let deleted: Vec<_> = deleted.into_iter().map(|p| p.meta_key).collect();
+		assert_eq!(deleted, ["dist/x86_64/foo_1.2.meta"]);
+	}
+}
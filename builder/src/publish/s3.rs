@@ -0,0 +1,85 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Publishing to a S3 bucket.
+//!
+//! Any S3-compatible storage can be used by specifying a custom endpoint.
+
+use super::Backend;
+use ::s3::{creds::Credentials, Bucket, Region};
+use common::anyhow::Result;
+use std::str::FromStr;
+
+/// A S3 bucket.
+pub struct S3 {
+	bucket: Box<Bucket>,
+}
+
+impl S3 {
+	/// Opens the bucket.
+	///
+	/// Arguments:
+	/// - `name` is the name of the bucket
+	/// - `region` is the region of the bucket
+	/// - `endpoint` is the endpoint of the bucket. If `None`, the endpoint is deduced from the
+	///   region
+	///
+	/// Credentials are taken from the environment.
+	pub fn new(name: &str, region: String, endpoint: Option<String>) -> Result<Self> {
+		let region = match endpoint {
+			Some(endpoint) => Region::Custom {
+				region,
+				endpoint,
+			},
+			None => Region::from_str(&region)?,
+		};
+		let credentials = Credentials::default()?;
+		let bucket = Bucket::new(name, region, credentials)?;
+		Ok(Self {
+			bucket,
+		})
+	}
+}
+
+impl Backend for S3 {
+	async fn upload(&self, key: &str, data: &[u8]) -> Result<()> {
+		self.bucket.put_object(key, data).await?;
+		Ok(())
+	}
+
+	async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+		let keys = self
+			.bucket
+			.list(prefix.to_owned(), None)
+			.await?
+			.into_iter()
+			.flat_map(|r| r.contents)
+			.map(|o| o.key)
+			.collect();
+		Ok(keys)
+	}
+
+	async fn get(&self, key: &str) -> Result<Vec<u8>> {
+		Ok(self.bucket.get_object(key).await?.to_vec())
+	}
+
+	async fn delete(&self, key: &str) -> Result<()> {
+		self.bucket.delete_object(key).await?;
+		Ok(())
+	}
+}
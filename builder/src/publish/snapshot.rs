@@ -0,0 +1,61 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Snapshots are immutable copies of a repository's index.
+//!
+//! A snapshot references the archives of the repository as they were when the snapshot was
+//! created, which allows clients to install the exact same set of packages later on, even after
+//! the repository's index has changed.
+
+use super::{snapshot_index_key, Backend, INDEX_KEY};
+use crate::util::UtcTime;
+use common::{
+	anyhow::{anyhow, bail, Result},
+	repository::is_valid_snapshot_id,
+};
+
+/// Returns an ID for a snapshot created now, in the form `YYYY-MM-DDTHHMMSSZ` (UTC).
+pub fn default_id() -> String {
+	let t = UtcTime::now();
+	format!(
+		"{:04}-{:02}-{:02}T{:02}{:02}{:02}Z",
+		t.year, t.month, t.day, t.hour, t.minute, t.second
+	)
+}
+
+/// Creates a snapshot of the current index of the repository published on `backend`, with the
+/// given `id`.
+///
+/// Since snapshots are immutable, the function fails if a snapshot with the same ID already
+/// exists.
+pub async fn create<B: Backend>(backend: &B, id: &str) -> Result<()> {
+	if !is_valid_snapshot_id(id) {
+		bail!("invalid snapshot ID: {id}");
+	}
+	let key = snapshot_index_key(id);
+	let exists = backend.list(&key).await?.contains(&key);
+	if exists {
+		bail!("snapshot `{id}` already exists");
+	}
+	let index = backend
+		.get(INDEX_KEY)
+		.await
+		.map_err(|e| anyhow!("cannot read the repository's index: {e}"))?;
+	println!("Upload `{key}`...");
+	backend.upload(&key, &index).await
+}
@@ -0,0 +1,128 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Publishing to a directory on a remote host, through SSH.
+//!
+//! Files are uploaded and managed by running `cat`, `mkdir`, `mv`, `find` and `rm` on the remote
+//! host, so that the remote host needs to provide a POSIX shell over SSH. Hosts allowing only SFTP
+//! or rsync are not supported.
+
+use super::Backend;
+use common::anyhow::{anyhow, bail, Result};
+use std::{
+	io::Write,
+	process::{Command, Stdio},
+};
+
+/// Quotes `s` so that it is interpreted literally by a POSIX shell.
+fn quote(s: &str) -> String {
+	format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// A directory on a remote host.
+pub struct Ssh {
+	/// The destination passed to `ssh`, in the form `[user@]host`.
+	destination: String,
+	/// The path to the root of the repository on the remote host.
+	path: String,
+}
+
+impl Ssh {
+	/// Parses the remote location `remote`, in the form `[user@]host:path`.
+	pub fn new(remote: &str) -> Result<Self> {
+		let Some((destination, path)) = remote.split_once(':') else {
+			bail!("invalid remote `{remote}`: expected `[user@]host:path`");
+		};
+		if destination.is_empty() {
+			bail!("invalid remote `{remote}`: missing host");
+		}
+		let path = if path.is_empty() { "." } else { path };
+		Ok(Self {
+			destination: destination.to_owned(),
+			path: path.trim_end_matches('/').to_owned(),
+		})
+	}
+
+	/// Returns the path on the remote host of the file at `key`.
+	fn remote_path(&self, key: &str) -> String {
+		format!("{}/{key}", self.path)
+	}
+
+	/// Runs the shell command `cmd` on the remote host, writing `input` on its standard input.
+	///
+	/// The function returns the standard output of the command.
+	fn run(&self, cmd: &str, input: &[u8]) -> Result<Vec<u8>> {
+		let mut child = Command::new("ssh")
+			.arg("--")
+			.arg(&self.destination)
+			.arg(cmd)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::piped())
+			.spawn()
+			.map_err(|e| anyhow!("cannot run `ssh`: {e}"))?;
+		// Dropping stdin closes it, signaling the end of the input to the remote command
+		child.stdin.take().unwrap().write_all(input)?;
+		let output = child.wait_with_output()?;
+		if !output.status.success() {
+			bail!("command failed on `{}`: {cmd}", self.destination);
+		}
+		Ok(output.stdout)
+	}
+}
+
+impl Backend for Ssh {
+	async fn upload(&self, key: &str, data: &[u8]) -> Result<()> {
+		let path = self.remote_path(key);
+		let tmp_path = format!("{path}.tmp");
+		let cmd = format!(
+			"mkdir -p \"$(dirname {path})\" && cat >{tmp} && mv -f {tmp} {path}",
+			path = quote(&path),
+			tmp = quote(&tmp_path),
+		);
+		self.run(&cmd, data)?;
+		Ok(())
+	}
+
+	async fn list(&self, prefix: &str) -> Result<Vec<String>> {
+		let cmd = format!(
+			"cd {} 2>/dev/null || exit 0; find . -type f",
+			quote(&self.path)
+		);
+		let output = self.run(&cmd, &[])?;
+		let output = String::from_utf8(output)
+			.map_err(|_| anyhow!("remote `{}` returned invalid UTF-8", self.destination))?;
+		let keys = output
+			.lines()
+			.filter_map(|l| l.strip_prefix("./"))
+			.filter(|k| k.starts_with(prefix))
+			.map(str::to_owned)
+			.collect();
+		Ok(keys)
+	}
+
+	async fn get(&self, key: &str) -> Result<Vec<u8>> {
+		let cmd = format!("cat {}", quote(&self.remote_path(key)));
+		self.run(&cmd, &[])
+	}
+
+	async fn delete(&self, key: &str) -> Result<()> {
+		let cmd = format!("rm -f {}", quote(&self.remote_path(key)));
+		self.run(&cmd, &[])?;
+		Ok(())
+	}
+}
@@ -0,0 +1,128 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Verification of the reproducibility of packages.
+//!
+//! A package is reproducible if building it twice from the same inputs produces the same
+//! archive. To verify it, the package is built twice, in different build directories, then the
+//! results are compared.
+
+use crate::{
+	archive, build_all::read_package, get_arch, util::get_build_triplet, BuildArgs,
+	VerifyReproducibleArgs,
+};
+use common::{
+	anyhow::{bail, Result},
+	repository::Repository,
+	util::create_tmp_dir,
+};
+use std::{env, fs, path::Path};
+
+/// Prints the differences between the archives at `a` and `b`.
+///
+/// The function returns the number of differences found.
+fn diff_archives(a: &Path, b: &Path) -> Result<usize> {
+	let a = archive::list_entries(a)?;
+	let b = archive::list_entries(b)?;
+	let mut count = 0;
+	for (path, entry) in &a {
+		match b.get(path) {
+			None => println!("- `{path}`: only in the first build"),
+			Some(other) if other != entry => {
+				println!("- `{path}`:\n\tfirst build: {entry:?}\n\tsecond build: {other:?}");
+			}
+			Some(_) => continue,
+		}
+		count += 1;
+	}
+	for path in b.keys().filter(|p| !a.contains_key(*p)) {
+		println!("- `{path}`: only in the second build");
+		count += 1;
+	}
+	Ok(count)
+}
+
+/// Builds the package described by `args` twice and compares the results.
+pub fn verify(args: VerifyReproducibleArgs) -> Result<()> {
+	let mut package = read_package(&args.from)?;
+	package.archive_format = args.archive_format;
+	let build = get_build_triplet(args.build.as_deref())?;
+	let arch = get_arch(args.host.as_deref().unwrap_or(&build)).to_owned();
+	fs::create_dir_all(&args.work_dir)?;
+	// Both builds happen at different times: modification times must be clamped to compare them
+	if archive::source_date_epoch()?.is_none() {
+		env::set_var("SOURCE_DATE_EPOCH", "0");
+	}
+	let outputs = [
+		create_tmp_dir(&args.work_dir)?,
+		create_tmp_dir(&args.work_dir)?,
+	];
+	for (i, to) in outputs.iter().enumerate() {
+		println!("[INFO] Build {}/{}...", i + 1, outputs.len());
+		crate::build(BuildArgs {
+			from: args.from.clone(),
+			to: to.clone(),
+			package: true,
+			jobs: args.jobs,
+			build: Some(build.clone()),
+			host: args.host.clone(),
+			target: args.target.clone(),
+			chroot: args.chroot,
+			sandbox: args.sandbox,
+			force: true,
+			archive_format: args.archive_format,
+			features: args.features.clone(),
+			no_default_features: args.no_default_features,
+			check: false,
+			ignore_check: false,
+			debug: false,
+			work_dir: args.work_dir.clone(),
+		})?;
+	}
+
+	println!("[INFO] Compare builds...");
+	let [first, second] = outputs.map(Repository::local);
+	let read_metadata = |repo: &Repository| {
+		fs::read(repo.get_metadata_path(&arch, &package.name, &package.version))
+	};
+	let first_archive = first.get_archive_path(&arch, &package);
+	let second_archive = second.get_archive_path(&arch, &package);
+	let mut differences = 0;
+	if read_metadata(&first)? != read_metadata(&second)? {
+		println!("- metadata differ");
+		differences += 1;
+	}
+	if fs::read(&first_archive)? != fs::read(&second_archive)? {
+		let count = diff_archives(&first_archive, &second_archive)?;
+		if count == 0 {
+			println!("- archives have the same entries, but different encodings");
+		}
+		differences += count.max(1);
+	}
+	if differences > 0 {
+		bail!(
+			"package is not reproducible: {differences} difference(s) found between `{}` and `{}`",
+			first.get_path().display(),
+			second.get_path().display()
+		);
+	}
+	fs::remove_dir_all(first.get_path())?;
+	fs::remove_dir_all(second.get_path())?;
+	println!("[INFO] Package is reproducible");
+	Ok(())
+}
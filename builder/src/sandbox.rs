@@ -0,0 +1,263 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Unprivileged build sandbox.
+//!
+//! The sandbox relies on user and mount namespaces, so that it does not require root privileges.
+//! Inside of it, the build hook runs as root, chrooted in the sysroot. The sysroot is mounted
+//! read-only, except for the build and install directories. Device nodes are bind-mounted from
+//! the host, and `/tmp` is private to the build.
+//!
+//! Isolated hooks may also be deprived of network access, by running them in a network namespace
+//! with only a loopback interface.
+
+use std::{
+	ffi::{CStr, CString},
+	fs::{self, File},
+	io, mem,
+	mem::MaybeUninit,
+	os::unix::ffi::OsStrExt,
+	path::Path,
+	ptr::null,
+};
+
+/// The device nodes bind-mounted from the host, relative to the root.
+const DEV_NODES: &[&str] = &[
+	"dev/null",
+	"dev/zero",
+	"dev/full",
+	"dev/random",
+	"dev/urandom",
+	"dev/tty",
+];
+
+/// Converts `path` into a C string.
+fn to_cstring(path: &Path) -> io::Result<CString> {
+	CString::new(path.as_os_str().as_bytes())
+		.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Converts the return value `rc` of a system call into a result.
+fn check(rc: libc::c_int) -> io::Result<()> {
+	if rc < 0 {
+		Err(io::Error::last_os_error())
+	} else {
+		Ok(())
+	}
+}
+
+/// Returns the mount flags of the filesystem containing `path` that are kept when remounting it
+/// from a user namespace.
+fn locked_mount_flags(path: &CStr) -> io::Result<libc::c_ulong> {
+	let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+	check(unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) })?;
+	let stat = unsafe { stat.assume_init() };
+	let flags = [
+		(libc::ST_NOSUID, libc::MS_NOSUID),
+		(libc::ST_NODEV, libc::MS_NODEV),
+		(libc::ST_NOEXEC, libc::MS_NOEXEC),
+		(libc::ST_NOATIME, libc::MS_NOATIME),
+		(libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+	]
+	.into_iter()
+	.filter(|(st, _)| stat.f_flag & st != 0)
+	.fold(0, |flags, (_, ms)| flags | ms);
+	Ok(flags)
+}
+
+/// Bind-mounts `src` onto `dst`.
+fn bind(src: &CStr, dst: &CStr, flags: libc::c_ulong) -> io::Result<()> {
+	check(unsafe {
+		libc::mount(
+			src.as_ptr(),
+			dst.as_ptr(),
+			null(),
+			libc::MS_BIND | flags,
+			null(),
+		)
+	})
+}
+
+/// Writes `content` to the file at `path`, without allocating memory.
+fn write_file(path: &CStr, content: &[u8]) -> io::Result<()> {
+	let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC) };
+	check(fd)?;
+	let rc = unsafe { libc::write(fd, content.as_ptr() as _, content.len()) };
+	unsafe {
+		libc::close(fd);
+	}
+	if rc < 0 {
+		return Err(io::Error::last_os_error());
+	}
+	Ok(())
+}
+
+/// Brings the loopback interface of the current network namespace up.
+fn loopback_up() -> io::Result<()> {
+	let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+	check(fd)?;
+	let mut req: libc::ifreq = unsafe { mem::zeroed() };
+	for (dst, src) in req.ifr_name.iter_mut().zip(b"lo") {
+		*dst = *src as _;
+	}
+	req.ifr_ifru.ifru_flags = (libc::IFF_UP | libc::IFF_RUNNING) as _;
+	let rc = unsafe { libc::ioctl(fd, libc::SIOCSIFFLAGS as _, &req) };
+	unsafe {
+		libc::close(fd);
+	}
+	check(rc)
+}
+
+/// Moves the current process to a new network namespace, in which only the loopback interface is
+/// available.
+///
+/// This function is meant to be called in the forked process, right before executing a hook. It
+/// requires the `CAP_SYS_ADMIN` and `CAP_NET_ADMIN` capabilities.
+pub fn isolate_network() -> io::Result<()> {
+	check(unsafe { libc::unshare(libc::CLONE_NEWNET) })?;
+	loopback_up()
+}
+
+/// Creates the mount points of the sandbox in `sysroot`.
+///
+/// Contrary to a chroot, the sandbox does not require device nodes to be created in the sysroot.
+pub fn prepare(sysroot: &Path) -> io::Result<()> {
+	for rel in DEV_NODES {
+		let path = sysroot.join(rel);
+		if !path.exists() {
+			File::create(path)?;
+		}
+	}
+	fs::create_dir_all(sysroot.join("tmp"))
+}
+
+/// A sandbox in which a build hook runs.
+///
+/// All the paths are prepared in advance, because entering the sandbox happens in a forked
+/// process, where allocating memory is not allowed.
+pub struct Sandbox {
+	/// The path to the sysroot.
+	sysroot: CString,
+	/// The mount flags to keep when remounting the sysroot.
+	sysroot_flags: libc::c_ulong,
+	/// The directories that remain writable.
+	writable: Vec<CString>,
+	/// Device nodes, with the path on the host and the path of the mount point.
+	dev_nodes: Vec<(CString, CString)>,
+	/// The path to the sandbox's `/tmp`.
+	tmp: CString,
+	/// The working directory, relative to the sysroot.
+	cwd: CString,
+	/// The content of the user ID map.
+	uid_map: String,
+	/// The content of the group ID map.
+	gid_map: String,
+	/// Tells whether the sandbox has network access.
+	network: bool,
+}
+
+impl Sandbox {
+	/// Creates a new instance.
+	///
+	/// Arguments:
+	/// - `sysroot` is the path to the sysroot, which becomes the root of the sandbox
+	/// - `writable` is the list of directories in the sysroot that remain writable
+	/// - `cwd` is the working directory of the hook, relative to the sysroot
+	/// - `network` tells whether the hook has network access
+	pub fn new(sysroot: &Path, writable: &[&Path], cwd: &Path, network: bool) -> io::Result<Self> {
+		let sysroot_c = to_cstring(sysroot)?;
+		let sysroot_flags = locked_mount_flags(&sysroot_c)?;
+		let writable = writable
+			.iter()
+			.map(|p| to_cstring(p))
+			.collect::<io::Result<_>>()?;
+		let dev_nodes = DEV_NODES
+			.iter()
+			.map(|rel| {
+				let src = to_cstring(&Path::new("/").join(rel))?;
+				let dst = to_cstring(&sysroot.join(rel))?;
+				Ok((src, dst))
+			})
+			.collect::<io::Result<_>>()?;
+		// The current user becomes root in the sandbox
+		let uid = unsafe { libc::getuid() };
+		let gid = unsafe { libc::getgid() };
+		Ok(Self {
+			sysroot: sysroot_c,
+			sysroot_flags,
+			writable,
+			dev_nodes,
+			tmp: to_cstring(&sysroot.join("tmp"))?,
+			cwd: to_cstring(cwd)?,
+			uid_map: format!("0 {uid} 1\n"),
+			gid_map: format!("0 {gid} 1\n"),
+			network,
+		})
+	}
+
+	/// Enters the sandbox.
+	///
+	/// This function is meant to be called in the forked process, right before executing the hook.
+	pub fn enter(&self) -> io::Result<()> {
+		check(unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNS) })?;
+		// Denying `setgroups` is required to write the group ID map
+		match write_file(c"/proc/self/setgroups", b"deny") {
+			Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+			_ => {}
+		}
+		write_file(c"/proc/self/uid_map", self.uid_map.as_bytes())?;
+		write_file(c"/proc/self/gid_map", self.gid_map.as_bytes())?;
+		if !self.network {
+			isolate_network()?;
+		}
+		// Do not propagate mounts to the host
+		check(unsafe {
+			libc::mount(
+				null(),
+				c"/".as_ptr(),
+				null(),
+				libc::MS_REC | libc::MS_PRIVATE,
+				null(),
+			)
+		})?;
+		bind(&self.sysroot, &self.sysroot, libc::MS_REC)?;
+		for dir in &self.writable {
+			bind(dir, dir, 0)?;
+		}
+		for (src, dst) in &self.dev_nodes {
+			bind(src, dst, 0)?;
+		}
+		check(unsafe {
+			libc::mount(
+				c"tmpfs".as_ptr(),
+				self.tmp.as_ptr(),
+				c"tmpfs".as_ptr(),
+				libc::MS_NOSUID | libc::MS_NODEV,
+				null(),
+			)
+		})?;
+		// Mounts on top of the sysroot are not affected by this
+		bind(
+			&self.sysroot,
+			&self.sysroot,
+			libc::MS_REMOUNT | libc::MS_RDONLY | self.sysroot_flags,
+		)?;
+		check(unsafe { libc::chroot(self.sysroot.as_ptr()) })?;
+		check(unsafe { libc::chdir(self.cwd.as_ptr()) })
+	}
+}
@@ -18,16 +18,64 @@
 
 //! Utilities.
 
-use crate::BuildArgs;
 use core::str;
-use std::{env, ffi::OsStr, io, num::NonZeroUsize, process::Command, thread};
+use std::{
+	env,
+	ffi::OsStr,
+	io,
+	num::NonZeroUsize,
+	process::Command,
+	thread,
+	time::{SystemTime, UNIX_EPOCH},
+};
 
 /// Default build triplet in case it cannot be retrieved.
 const DEFAULT_BUILD_TRIPLET: &str = "x86_64-linux-gnu";
 
+/// A date and time, in UTC.
+pub struct UtcTime {
+	pub year: i64,
+	pub month: i64,
+	pub day: i64,
+	pub hour: u64,
+	pub minute: u64,
+	pub second: u64,
+}
+
+impl UtcTime {
+	/// Returns the current date and time.
+	pub fn now() -> Self {
+		let secs = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+		let (days, time) = (secs / 86400, secs % 86400);
+		// Convert the number of days since the epoch to a civil date (Howard Hinnant's algorithm)
+		let z = days as i64 + 719468;
+		let era = z.div_euclid(146097);
+		let doe = z.rem_euclid(146097);
+		let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+		let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+		let mp = (5 * doy + 2) / 153;
+		let day = doy - (153 * mp + 2) / 5 + 1;
+		let month = if mp < 10 { mp + 3 } else { mp - 9 };
+		let year = yoe + era * 400 + (month <= 2) as i64;
+		Self {
+			year,
+			month,
+			day,
+			hour: time / 3600,
+			minute: time / 60 % 60,
+			second: time % 60,
+		}
+	}
+}
+
 /// Returns the recommended amount of CPUs to build the package.
-pub fn get_jobs_count(args: &BuildArgs) -> usize {
-	args.jobs.unwrap_or_else(|| {
+///
+/// `jobs` is the amount specified by the user, if any.
+pub fn get_jobs_count(jobs: Option<usize>) -> usize {
+	jobs.unwrap_or_else(|| {
 		// Not specified by the user: get the amount of CPU on the system
 		thread::available_parallelism()
 			.map(NonZeroUsize::get)
@@ -47,9 +95,11 @@ fn get_build_triplet_from_cc() -> io::Result<Option<String>> {
 }
 
 /// Returns the triplet of the machine on which the package is being built.
-pub fn get_build_triplet(args: &BuildArgs) -> io::Result<String> {
-	if let Some(triplet) = &args.build {
-		return Ok(triplet.clone());
+///
+/// `build` is the triplet specified by the user, if any.
+pub fn get_build_triplet(build: Option<&str>) -> io::Result<String> {
+	if let Some(triplet) = build {
+		return Ok(triplet.to_owned());
 	}
 	if let Some(triplet) = get_build_triplet_from_cc()? {
 		return Ok(triplet);
@@ -0,0 +1,266 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Application of a manifest describing the desired state of the system.
+
+use crate::confirm;
+use common::{
+	anyhow::{bail, Result},
+	manifest::Manifest,
+	package::{DependencyType, InstalledPackage},
+	repository::{
+		self, get_recursive_dependencies, PackagesWithRepositoryMap, PackagesWithRepositoryVec,
+	},
+	Environment,
+};
+use std::{
+	collections::{HashMap, HashSet},
+	path::Path,
+};
+
+/// Computes the changes bringing the installed packages to the state described by a manifest.
+///
+/// Arguments:
+/// - `installed` is the installed packages, by name
+/// - `satisfied` is the names of the installed packages satisfying the manifest
+/// - `resolved` is the packages resolved for the rest of the manifest, dependencies included
+///
+/// The function returns the packages to install or upgrade, sorted by name, and the explicitly
+/// installed packages to remove, which are neither needed by the manifest nor a dependency of a
+/// needed package.
+fn diff<'i, 'r>(
+	installed: &'i HashMap<String, InstalledPackage>,
+	satisfied: &[&str],
+	resolved: PackagesWithRepositoryMap<'r>,
+) -> (PackagesWithRepositoryVec<'r>, Vec<&'i InstalledPackage>) {
+	// The packages needed by the manifest: the resolved packages, and the installed packages
+	// satisfying the manifest along with their dependencies, recursively
+	let mut kept: HashSet<String> = HashSet::new();
+	let mut queue: Vec<String> = satisfied
+		.iter()
+		.map(|name| name.to_string())
+		.chain(resolved.keys().map(|p| p.name.clone()))
+		.collect();
+	while let Some(name) = queue.pop() {
+		if !kept.insert(name.clone()) {
+			continue;
+		}
+		let Some(inst) = installed.get(&name) else {
+			continue;
+		};
+		let deps = inst.desc.deps.iter().filter(|dep| {
+			!matches!(
+				dep.dep_type,
+				DependencyType::Build | DependencyType::Optional | DependencyType::Check
+			)
+		});
+		for dep in deps {
+			let satisfied_by = installed
+				.values()
+				.find(|i| i.desc.satisfies(&dep.name, &dep.version_constraint));
+			if let Some(i) = satisfied_by {
+				queue.push(i.desc.name.clone());
+			}
+		}
+	}
+	// Dependencies already installed with the resolved version do not need to be reinstalled
+	let mut to_install: Vec<_> = resolved
+		.into_iter()
+		.filter(|(pkg, _)| {
+			installed
+				.get(&pkg.name)
+				.is_none_or(|inst| inst.desc.version != pkg.version)
+		})
+		.collect();
+	to_install.sort_unstable_by(|(p0, _), (p1, _)| p0.name.cmp(&p1.name));
+	let mut to_remove: Vec<_> = installed
+		.values()
+		.filter(|inst| inst.explicit && !kept.contains(inst.desc.name.as_str()))
+		.collect();
+	to_remove.sort_unstable_by(|i0, i1| i0.desc.name.cmp(&i1.desc.name));
+	(to_install, to_remove)
+}
+
+/// Brings the environment to the state described by the manifest at `path`.
+///
+/// Missing packages are installed, packages whose installed version does not match the
+/// manifest's constraints are upgraded, and explicitly installed packages that are not listed in
+/// the manifest are removed.
+pub async fn apply(path: &Path, env: &mut Environment) -> Result<()> {
+	let manifest = Manifest::load(path)?;
+	let repos = env.list_repositories()?;
+	let installed = env.list_installed()?;
+	let mut failed = false;
+	let mut packages = PackagesWithRepositoryMap::new();
+	let mut to_mark = vec![];
+	// Names of the installed packages satisfying the manifest
+	let mut satisfied = vec![];
+	for wanted in &manifest.package {
+		// The package may be wanted through a capability it provides
+		let inst = installed.values().find(|inst| {
+			inst.desc
+				.satisfies(&wanted.name, &wanted.version_constraint)
+		});
+		if let Some(inst) = inst {
+			if !inst.explicit {
+				to_mark.push(inst.desc.name.as_str());
+			}
+			satisfied.push(inst.desc.name.as_str());
+			continue;
+		}
+		let res = repository::get_package_with_constraint(
+			&repos,
+			env.arch(),
+			&wanted.name,
+			Some(&wanted.version_constraint),
+		)?;
+		let Some((repo, pkg)) = res else {
+			eprintln!(
+				"Package `{}` not found for constraint `{}`!",
+				wanted.name, wanted.version_constraint
+			);
+			failed = true;
+			continue;
+		};
+		packages.insert(pkg, repo);
+	}
+	if failed {
+		bail!("cannot apply manifest");
+	}
+
+	println!("Resolving dependencies...");
+	let total_packages =
+		get_recursive_dependencies(&packages, &repos, DependencyType::Run, env.arch())?;
+	let (total_packages, to_remove) = diff(&installed, &satisfied, total_packages);
+
+	if total_packages.is_empty() && to_remove.is_empty() {
+		for name in to_mark {
+			env.set_explicit(name, true)?;
+		}
+		println!("The environment already matches the manifest.");
+		return Ok(());
+	}
+	let (to_upgrade, to_install): (Vec<_>, Vec<_>) = total_packages
+		.iter()
+		.partition(|(pkg, _)| installed.contains_key(&pkg.name));
+	if !to_install.is_empty() {
+		println!("Packages to be installed:");
+		for (pkg, _) in &to_install {
+			println!("\t- {} {}", pkg.name, pkg.version);
+		}
+	}
+	if !to_upgrade.is_empty() {
+		println!("Packages to be upgraded:");
+		for (pkg, _) in &to_upgrade {
+			let old = &installed[&pkg.name].desc.version;
+			println!("\t- {} {old} -> {}", pkg.name, pkg.version);
+		}
+	}
+	if !to_remove.is_empty() {
+		println!("Packages to be removed:");
+		for inst in &to_remove {
+			println!("\t- {} {}", inst.desc.name, inst.desc.version);
+		}
+	}
+	#[cfg(feature = "network")]
+	crate::install::print_download_size(&total_packages, env.arch()).await?;
+	if !confirm::prompt() {
+		println!("Aborting.");
+		return Ok(());
+	}
+	#[cfg(feature = "network")]
+	{
+		use common::repository::remote::download_packages;
+
+		println!("Downloading packages...");
+		download_packages(&total_packages, env.arch()).await?;
+	}
+	println!();
+	for inst in to_remove {
+		println!("Removing `{}`...", inst.desc.name);
+		env.remove(inst)?;
+	}
+	for name in to_mark {
+		env.set_explicit(name, true)?;
+	}
+	// Upgraded packages are installed over their previous version
+	println!("Installing packages...");
+	let explicit: Vec<String> = packages.into_keys().map(|p| p.name).collect();
+	env.install_packages(&total_packages, &explicit)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use common::{package::Package, repository::Repository};
+
+	fn package(name: &str, version: &str, deps: &[&str]) -> Package {
+		let deps: Vec<_> = deps
+			.iter()
+			.map(|d| format!("{{ type = \"run\", name = \"{d}\", version = \"*\" }}"))
+			.collect();
+		Package::parse(&format!(
+			"name = \"{name}\"\nversion = \"{version}\"\ndescription = \"\"\ndep = [{}]",
+			deps.join(", ")
+		))
+		.unwrap()
+	}
+
+	#[test]
+	fn install_remove_diff() {
+		let installed: HashMap<_, _> = [
+			("app", true, &["libfoo"][..]),
+			("libfoo", false, &[]),
+			("old", true, &["libold"]),
+			("libold", false, &[]),
+			("tool", true, &[]),
+		]
+		.into_iter()
+		.map(|(name, explicit, deps)| {
+			let inst = InstalledPackage {
+				desc: package(name, "1.0", deps),
+				files: vec![],
+				manifest: vec![],
+				repository: None,
+				sha256: None,
+				explicit,
+			};
+			(name.to_owned(), inst)
+		})
+		.collect();
+		let repo = Repository::local("/nonexistent".into());
+		// The manifest wants `app` as installed, `tool` upgraded and the new package `new`
+		let resolved = [
+			package("tool", "2.0", &[]),
+			package("new", "1.0", &["libfoo"]),
+			package("libfoo", "1.0", &[]),
+		]
+		.into_iter()
+		.map(|p| (p, &repo))
+		.collect();
+		let (to_install, to_remove) = diff(&installed, &["app"], resolved);
+		let to_install: Vec<_> = to_install
+			.iter()
+			.map(|(p, _)| format!("{} {}", p.name, p.version))
+			.collect();
+		assert_eq!(to_install, ["new 1.0", "tool 2.0"]);
+		// Packages installed as dependencies are not removed explicitly
+		let to_remove: Vec<_> = to_remove.iter().map(|i| i.desc.name.as_str()).collect();
+		assert_eq!(to_remove, ["old"]);
+	}
+}
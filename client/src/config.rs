@@ -0,0 +1,39 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Handling of configuration files kept on upgrade.
+
+use common::{anyhow::Result, file_manifest::NEW_SUFFIX, Environment};
+
+/// Lists configuration files waiting to be merged with their new version.
+///
+/// When a package is upgraded, locally modified configuration files are kept, and their new
+/// version is written next to them, with the [`NEW_SUFFIX`] suffix.
+pub fn diff(env: &Environment) -> Result<()> {
+	let pending = env.pending_configs()?;
+	for (name, path) in &pending {
+		println!(
+			"{name}: /{} (new version: /{}{NEW_SUFFIX})",
+			path.display(),
+			path.display()
+		);
+	}
+	if pending.is_empty() {
+		println!("No pending configuration file");
+	}
+	Ok(())
+}
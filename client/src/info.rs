@@ -0,0 +1,116 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! This module handles printing information about packages.
+
+use common::{
+	anyhow::{bail, Result},
+	package::{DependencyType, Package},
+	repository, Environment,
+};
+
+/// Prints information about the given list of packages.
+///
+/// For each package, the latest version available in repositories is described. If the package
+/// is not available, the installed version is described instead.
+///
+/// Arguments:
+/// - `names` is the list of packages.
+/// - `env` is the blimp environment.
+pub fn info(names: &[String], env: &Environment) -> Result<()> {
+	if names.is_empty() {
+		bail!("must specify at least one package");
+	}
+	let repos = env.list_repositories()?;
+	let installed = env.list_installed()?;
+	let mut failed = false;
+	for (i, name) in names.iter().enumerate() {
+		let available = repository::get_package_with_constraint(&repos, env.arch(), name, None)?
+			.map(|(_, pkg)| pkg);
+		let inst = installed.get(name);
+		let Some(pkg) = available.as_ref().or(inst.map(|i| &i.desc)) else {
+			eprintln!("Package `{name}` not found!");
+			failed = true;
+			continue;
+		};
+		if i > 0 {
+			println!();
+		}
+		print_package(pkg);
+		match inst {
+			Some(inst) => println!("Installed: {}", inst.desc.version),
+			None => println!("Installed: no"),
+		}
+		let required: Vec<_> = pkg
+			.deps
+			.iter()
+			.filter(|d| d.dep_type != DependencyType::Optional)
+			.collect();
+		if !required.is_empty() {
+			println!("Dependencies:");
+			for d in required {
+				println!("\t- {d} ({})", d.dep_type);
+			}
+		}
+		let optional: Vec<_> = pkg.optional_deps().collect();
+		if !optional.is_empty() {
+			println!("Optional dependencies:");
+			for d in optional {
+				let satisfied_by = installed
+					.values()
+					.find(|i| i.desc.satisfies(&d.name, &d.version_constraint));
+				match satisfied_by {
+					Some(i) => println!(
+						"\t- {d} [satisfied by `{}` {}]",
+						i.desc.name, i.desc.version
+					),
+					None => println!("\t- {d}"),
+				}
+			}
+		}
+	}
+	if failed {
+		bail!("cannot find every package");
+	}
+	Ok(())
+}
+
+/// Prints the description of `pkg` and its relations to other packages.
+fn print_package(pkg: &Package) {
+	println!("Name: {}", pkg.name);
+	println!("Version: {}", pkg.version);
+	println!("Description: {}", pkg.description);
+	let relations = [("Conflicts", &pkg.conflicts), ("Replaces", &pkg.replaces)];
+	for (title, list) in relations {
+		if !list.is_empty() {
+			let list: Vec<_> = list.iter().map(ToString::to_string).collect();
+			println!("{title}: {}", list.join(", "));
+		}
+	}
+	if !pkg.provides.is_empty() {
+		let list: Vec<_> = pkg
+			.provides
+			.iter()
+			.map(|p| match &p.version {
+				Some(v) => format!("{} {v}", p.name),
+				None => p.name.clone(),
+			})
+			.collect();
+		println!("Provides: {}", list.join(", "));
+	}
+}
@@ -22,14 +22,14 @@ use crate::confirm;
 use common::{
 	anyhow::{bail, Result},
 	maestro_utils::util::ByteSize,
-	package::{DependencyType, Package},
+	package::{self, Dependency, DependencyType, Package},
 	repository::{
 		self, get_recursive_dependencies, PackagesWithRepositoryMap, PackagesWithRepositoryVec,
 		Repository,
 	},
 	Environment,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// Get the list of packages to install.
 ///
@@ -58,11 +58,14 @@ fn packages_to_install<'r>(
 			failed = true;
 			continue;
 		};
-		packages.insert(pkg, repo);
 		// If already installed, print message
-		if let Some(version) = env.get_installed_version(name)? {
-			println!("Package `{name}` version `{version}` is already installed. Reinstalling");
+		if let Some(version) = env.get_installed_version(&pkg.name)? {
+			println!(
+				"Package `{}` version `{version}` is already installed. Reinstalling",
+				pkg.name
+			);
 		}
+		packages.insert(pkg, repo);
 	}
 	if failed {
 		bail!("installation failed");
@@ -77,7 +80,7 @@ fn packages_to_install<'r>(
 /// - `total_packages` is the whole list of packages to install
 /// - `arch` is the environment's architecture
 #[cfg(feature = "network")]
-async fn print_download_size<'r>(
+pub(crate) async fn print_download_size<'r>(
 	total_packages: &PackagesWithRepositoryVec<'r>,
 	arch: &str,
 ) -> Result<()> {
@@ -102,12 +105,34 @@ async fn print_download_size<'r>(
 	Ok(())
 }
 
+/// Returns the optional dependencies of `packages` which are satisfied neither by one of them nor
+/// by a package installed on `env`.
+fn missing_optional_deps<'p>(
+	packages: &'p PackagesWithRepositoryMap,
+	env: &Environment,
+) -> Result<Vec<&'p Dependency>> {
+	let installed = env.list_installed()?;
+	let mut deps: Vec<_> = packages
+		.keys()
+		.flat_map(Package::optional_deps)
+		.filter(|d| {
+			let satisfied = |p: &Package| p.satisfies(&d.name, &d.version_constraint);
+			!packages.keys().any(satisfied) && !installed.values().any(|i| satisfied(&i.desc))
+		})
+		.collect();
+	deps.sort_unstable_by(|d0, d1| d0.name.cmp(&d1.name));
+	deps.dedup_by(|d0, d1| d0.name == d1.name);
+	Ok(deps)
+}
+
 /// Installs the given list of packages.
 ///
 /// Arguments:
 /// - `names` is the list of packages to install.
+/// - `with_optional` tells whether optional dependencies are installed too. If not, they are only
+///   listed.
 /// - `env` is the blimp environment.
-pub async fn install(names: &[String], env: &mut Environment) -> Result<()> {
+pub async fn install(names: &[String], with_optional: bool, env: &mut Environment) -> Result<()> {
 	if names.is_empty() {
 		bail!("must specify at least one package");
 	}
@@ -115,8 +140,56 @@ pub async fn install(names: &[String], env: &mut Environment) -> Result<()> {
 	let packages = packages_to_install(names, &repos, env)?;
 
 	println!("Resolving dependencies...");
-	let total_packages =
+	let mut total_packages =
 		get_recursive_dependencies(&packages, &repos, DependencyType::Run, env.arch())?;
+	// Optional dependencies which are not available, to report them only once
+	let mut not_found = HashSet::new();
+	// Optional dependencies may have optional dependencies themselves
+	loop {
+		let mut optional = missing_optional_deps(&total_packages, env)?;
+		optional.retain(|d| !not_found.contains(&d.name));
+		if optional.is_empty() {
+			break;
+		}
+		if !with_optional {
+			println!("Optional dependencies (use `--with-optional` to install them):");
+			for d in optional {
+				println!("\t- {d}");
+			}
+			break;
+		}
+		let mut optional_packages = PackagesWithRepositoryMap::new();
+		for d in optional {
+			let res = repository::get_package_with_constraint(
+				&repos,
+				env.arch(),
+				&d.name,
+				Some(&d.version_constraint),
+			)?;
+			match res {
+				Some((repo, pkg)) => {
+					optional_packages.insert(pkg, repo);
+				}
+				None => {
+					eprintln!("warning: optional dependency `{d}` not found, ignored");
+					not_found.insert(d.name.clone());
+				}
+			}
+		}
+		if optional_packages.is_empty() {
+			break;
+		}
+		let deps = get_recursive_dependencies(
+			&optional_packages,
+			&repos,
+			DependencyType::Run,
+			env.arch(),
+		)?;
+		total_packages.extend(deps);
+	}
+	if let Some((name, other)) = package::list_conflicts(total_packages.keys()).first() {
+		bail!("package `{name}` conflicts with package `{other}`");
+	}
 	let mut total_packages: Vec<_> = total_packages.into_iter().collect();
 	total_packages.sort_unstable_by(|(p0, _), (p1, _)| p0.name.cmp(&p1.name));
 
@@ -125,7 +198,7 @@ pub async fn install(names: &[String], env: &mut Environment) -> Result<()> {
 	print_download_size(&total_packages, env.arch()).await?;
 	#[cfg(not(feature = "network"))]
 	{
-		for pkg in total_packages.keys() {
+		for (pkg, _) in &total_packages {
 			println!("\t- {} {} - cached", pkg.name, pkg.version);
 		}
 	}
@@ -142,5 +215,7 @@ pub async fn install(names: &[String], env: &mut Environment) -> Result<()> {
 	}
 	println!();
 	println!("Installing packages...");
-	env.install_packages(&total_packages)
+	// Packages may have been requested through a capability they provide
+	let explicit: Vec<String> = packages.into_keys().map(|p| p.name).collect();
+	env.install_packages(&total_packages, &explicit)
 }
@@ -0,0 +1,172 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Export and import of the exact set of installed packages.
+
+use crate::confirm;
+use common::{
+	anyhow::{bail, Result},
+	lockfile::{LockedPackage, Lockfile},
+	package::Package,
+	repository::{PackagesWithRepositoryVec, Repository},
+	util,
+	version::VersionConstraint,
+	Environment,
+};
+use std::{collections::HashSet, path::Path};
+
+/// Writes the lockfile of the environment on the standard output.
+pub fn export(env: &Environment) -> Result<()> {
+	let lockfile = Lockfile::from_env(env)?;
+	print!("{}", lockfile.to_toml()?);
+	Ok(())
+}
+
+/// Returns the package matching `locked` exactly, along with its repository.
+///
+/// The repository the package was originally installed from is looked up first.
+fn find_locked<'r>(
+	repos: &'r [Repository],
+	arch: &str,
+	locked: &LockedPackage,
+) -> Option<(Package, &'r Repository)> {
+	let constraint = VersionConstraint::Equal(locked.version.clone());
+	let is_origin = |r: &&Repository| locked.repository.as_deref() == Some(r.name().as_str());
+	let origin = repos.iter().filter(is_origin);
+	let others = repos.iter().filter(|r| !is_origin(r));
+	origin.chain(others).find_map(|repo| {
+		match repo.get_package_with_constraint(arch, &locked.name, Some(&constraint)) {
+			Ok(Some(pkg)) => Some((pkg, repo)),
+			_ => None,
+		}
+	})
+}
+
+/// Installs exactly the set of packages listed in the lockfile at `path`.
+///
+/// Installed packages that are not listed in the lockfile are removed.
+pub async fn import(path: &Path, env: &mut Environment) -> Result<()> {
+	let lockfile = Lockfile::load(path)?;
+	if lockfile.arch != env.arch() {
+		bail!(
+			"the lockfile is for architecture `{}`, but the environment uses `{}`",
+			lockfile.arch,
+			env.arch()
+		);
+	}
+	let repos = env.list_repositories()?;
+	let installed = env.list_installed()?;
+	let mut failed = false;
+	let mut to_install: PackagesWithRepositoryVec = vec![];
+	for locked in &lockfile.package {
+		// Skip packages that are already installed as required
+		let up_to_date = installed.get(&locked.name).is_some_and(|i| {
+			i.desc.version == locked.version
+				&& (locked.sha256.is_none() || i.sha256 == locked.sha256)
+		});
+		if up_to_date {
+			continue;
+		}
+		match find_locked(&repos, env.arch(), locked) {
+			Some(p) => to_install.push(p),
+			None => {
+				eprintln!(
+					"Package `{}` version `{}` is no longer available!",
+					locked.name, locked.version
+				);
+				failed = true;
+			}
+		}
+	}
+	if failed {
+		bail!("import failed");
+	}
+	let locked_names: HashSet<&str> = lockfile.package.iter().map(|p| p.name.as_str()).collect();
+	let mut to_remove: Vec<_> = installed
+		.values()
+		.filter(|i| !locked_names.contains(i.desc.name.as_str()))
+		.collect();
+	to_remove.sort_unstable_by(|i0, i1| i0.desc.name.cmp(&i1.desc.name));
+	if to_install.is_empty() && to_remove.is_empty() {
+		println!("The environment already matches the lockfile.");
+		return Ok(());
+	}
+	if !to_install.is_empty() {
+		println!("Packages to be installed:");
+		for (pkg, repo) in &to_install {
+			println!("\t- {} {} ({})", pkg.name, pkg.version, repo.name());
+		}
+	}
+	if !to_remove.is_empty() {
+		println!("Packages to be removed:");
+		for i in &to_remove {
+			println!("\t- {} {}", i.desc.name, i.desc.version);
+		}
+	}
+	if !confirm::prompt() {
+		println!("Aborting.");
+		return Ok(());
+	}
+	#[cfg(feature = "network")]
+	{
+		use common::repository::remote::download_packages;
+
+		println!("Downloading packages...");
+		download_packages(&to_install, env.arch()).await?;
+	}
+	// Check archives before modifying the environment
+	for (pkg, repo) in &to_install {
+		let locked = lockfile
+			.package
+			.iter()
+			.find(|p| p.name == pkg.name)
+			.unwrap();
+		let Some(expected) = &locked.sha256 else {
+			continue;
+		};
+		let archive_path = repo.get_archive_path(env.arch(), pkg);
+		let sha256 = util::sha256_file(&archive_path)?;
+		if sha256 != *expected {
+			bail!(
+				"archive of package `{}` version `{}` does not match the lockfile",
+				pkg.name,
+				pkg.version
+			);
+		}
+	}
+	println!();
+	for i in to_remove {
+		println!("Removing `{}`...", i.desc.name);
+		env.remove(i)?;
+	}
+	// Other versions of the packages are replaced in place, so that locally modified
+	// configuration files are preserved
+	println!("Installing packages...");
+	let explicit: Vec<String> = lockfile
+		.package
+		.iter()
+		.filter(|p| p.explicit)
+		.map(|p| p.name.clone())
+		.collect();
+	env.install_packages(&to_install, &explicit)?;
+	// Installing over a previous version keeps its `explicit` flag
+	for p in &lockfile.package {
+		env.set_explicit(&p.name, p.explicit)?;
+	}
+	Ok(())
+}
@@ -18,13 +18,18 @@
 
 //! Blimp is a simple package manager for Unix systems.
 
+mod apply;
+mod config;
 mod confirm;
+mod info;
 mod install;
+mod lockfile;
 #[cfg(feature = "network")]
 mod remote;
 mod remove;
 #[cfg(feature = "network")]
 mod update;
+mod verify;
 
 use clap::{Args, Parser, Subcommand};
 use common::{
@@ -33,6 +38,7 @@ use common::{
 	util::current_arch,
 	Environment,
 };
+use info::info;
 use install::install;
 use remove::remove;
 use std::{env, path::PathBuf, process::exit};
@@ -43,6 +49,15 @@ struct PkgList {
 	packages: Vec<String>,
 }
 
+#[derive(Args, Clone, Debug)]
+struct InstallArgs {
+	/// Packages
+	packages: Vec<String>,
+	/// Also install optional dependencies
+	#[arg(long)]
+	with_optional: bool,
+}
+
 #[derive(Clone, Debug, Subcommand)]
 enum Action {
 	/// Synchronizes packages information from remotes
@@ -51,7 +66,7 @@ enum Action {
 	/// Prints information about the given package(s)
 	Info(PkgList),
 	/// Installs the given package(s)
-	Install(PkgList),
+	Install(InstallArgs),
 	/// Upgrades the given package(s). If no package is specified, the package manager updates
 	/// every package that is not up-to-date
 	Upgrade(PkgList),
@@ -59,15 +74,47 @@ enum Action {
 	Remove(PkgList),
 	/// Cleans the cache
 	Clean,
+	/// Writes the exact set of installed packages to the standard output, in the lockfile format
+	Export,
+	/// Installs exactly the set of packages listed in the given lockfile, removing the others
+	Import { lockfile: PathBuf },
+	/// Brings the system to the state described by the given manifest: installs missing
+	/// packages, upgrades packages not matching their constraints and removes explicitly
+	/// installed packages that are not listed
+	Apply { manifest: PathBuf },
+	/// Lists configuration files kept on upgrade because they were modified locally, whose new
+	/// version is waiting to be merged
+	ConfigDiff,
+	/// Checks the installed files of the given package(s) against their file manifests. If no
+	/// package is specified, every installed package is checked
+	Verify {
+		packages: Vec<String>,
+		/// Print one difference per line, as tab-separated fields: package, kind of difference
+		/// and path
+		#[arg(long)]
+		porcelain: bool,
+	},
 	/// Lists remote servers
 	#[cfg(feature = "network")]
 	RemoteList,
 	/// Adds a remote server
 	#[cfg(feature = "network")]
-	RemoteAdd { remote: String },
+	RemoteAdd {
+		remote: String,
+		/// The ID of the snapshot to use on the remote, instead of its latest index
+		#[arg(long)]
+		snapshot: Option<String>,
+	},
 	/// Removes a remote server
 	#[cfg(feature = "network")]
 	RemoteRemove { remote: String },
+	/// Selects the snapshot to use on a remote server. If no snapshot is given, the latest index
+	/// of the remote is used
+	#[cfg(feature = "network")]
+	RemoteSnapshot {
+		remote: String,
+		snapshot: Option<String>,
+	},
 }
 
 #[derive(Parser, Debug)]
@@ -85,33 +132,55 @@ struct Cli {
 	/// The architecture to install for, defaults to the current
 	#[arg(short, long)]
 	arch: Option<String>,
+	/// The path to the system's root, defaults to the `SYSROOT` environment variable, or `/`
+	#[arg(long, global = true)]
+	sysroot: Option<PathBuf>,
 }
 
 async fn main_impl() -> Result<()> {
 	let args = Cli::parse();
-	let sysroot = env::var_os("SYSROOT")
-		.map(PathBuf::from)
+	let sysroot = args
+		.sysroot
+		.or_else(|| env::var_os("SYSROOT").map(PathBuf::from))
 		.unwrap_or(PathBuf::from("/"));
 	let mut env = Environment::acquire(&sysroot, args.arch.as_deref().unwrap_or(current_arch()))?
 		.ok_or_else(|| anyhow!("failed to acquire lockfile"))?;
 	match args.action {
 		#[cfg(feature = "network")]
 		Action::Update => update::update(&mut env).await?,
-		Action::Info(_names) => todo!(),
-		Action::Install(names) => install(&names.packages, &mut env).await?,
+		Action::Info(names) => info(&names.packages, &env)?,
+		Action::Install(args) => install(&args.packages, args.with_optional, &mut env).await?,
 		Action::Upgrade(_names) => todo!(),
 		Action::Remove(names) => remove(&names.packages, &mut env)?,
 		Action::Clean => todo!(),
+		Action::Export => lockfile::export(&env)?,
+		Action::Import {
+			lockfile,
+		} => lockfile::import(&lockfile, &mut env).await?,
+		Action::Apply {
+			manifest,
+		} => apply::apply(&manifest, &mut env).await?,
+		Action::ConfigDiff => config::diff(&env)?,
+		Action::Verify {
+			packages,
+			porcelain,
+		} => verify::verify(&packages, porcelain, &env)?,
 		#[cfg(feature = "network")]
 		Action::RemoteList => remote::list(&env).await?,
 		#[cfg(feature = "network")]
 		Action::RemoteAdd {
 			remote,
-		} => remote::add(&mut env, remote)?,
+			snapshot,
+		} => remote::add(&mut env, remote, snapshot)?,
 		#[cfg(feature = "network")]
 		Action::RemoteRemove {
 			remote,
 		} => remote::remove(&mut env, remote)?,
+		#[cfg(feature = "network")]
+		Action::RemoteSnapshot {
+			remote,
+			snapshot,
+		} => remote::snapshot(&mut env, remote, snapshot)?,
 	}
 	Ok(())
 }
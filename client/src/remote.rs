@@ -18,14 +18,32 @@
 
 //! Remotes management.
 
-use common::{repository::remote::Remote, Environment};
+use common::{
+	repository::{is_valid_snapshot_id, remote::Remote},
+	Environment,
+};
+use std::io;
+
+/// Returns an error if `snapshot` is not a valid snapshot ID.
+fn check_snapshot(snapshot: Option<&str>) -> io::Result<()> {
+	match snapshot {
+		Some(id) if !is_valid_snapshot_id(id) => Err(io::Error::new(
+			io::ErrorKind::InvalidInput,
+			format!("invalid snapshot ID: {id}"),
+		)),
+		_ => Ok(()),
+	}
+}
 
 /// Lists remotes.
-pub async fn list(env: &Environment) -> std::io::Result<()> {
+pub async fn list(env: &Environment) -> io::Result<()> {
 	let remotes = Remote::load_list(env)?;
 	println!("Remotes list:");
 	for remote in remotes {
-		let host = &remote.host;
+		let host = match &remote.snapshot {
+			Some(id) => format!("{} (snapshot: {id})", remote.host),
+			None => remote.host.clone(),
+		};
 		match remote.fetch_motd().await {
 			Ok(Some(motd)) => println!("- {host} (status: UP): {motd}"),
 			Ok(None) => println!("- {host} (status: UP)"),
@@ -40,7 +58,9 @@ pub async fn list(env: &Environment) -> std::io::Result<()> {
 /// Arguments:
 /// - `env` is the environment
 /// - `remote` is the remote to add
-pub fn add(env: &mut Environment, remote: String) -> std::io::Result<()> {
+/// - `snapshot` is the ID of the snapshot to use on the remote, if any
+pub fn add(env: &mut Environment, remote: String, snapshot: Option<String>) -> io::Result<()> {
+	check_snapshot(snapshot.as_deref())?;
 	let mut remotes = Remote::load_list(env)?;
 	if remotes.contains(remote.as_str()) {
 		eprintln!("Remote `{remote}` already exists");
@@ -48,6 +68,7 @@ pub fn add(env: &mut Environment, remote: String) -> std::io::Result<()> {
 		println!("Add remote `{remote}`");
 		remotes.insert(Remote {
 			host: remote,
+			snapshot,
 		});
 	}
 	Remote::save_list(env, remotes.into_iter())?;
@@ -59,7 +80,7 @@ pub fn add(env: &mut Environment, remote: String) -> std::io::Result<()> {
 /// Arguments:
 /// - `env` is the environment
 /// - `remote` is the remote to remove
-pub fn remove(env: &mut Environment, remote: String) -> std::io::Result<()> {
+pub fn remove(env: &mut Environment, remote: String) -> io::Result<()> {
 	let mut remotes = Remote::load_list(env)?;
 	let existed = remotes.remove(remote.as_str());
 	if !existed {
@@ -68,3 +89,31 @@ pub fn remove(env: &mut Environment, remote: String) -> std::io::Result<()> {
 	Remote::save_list(env, remotes.into_iter())?;
 	Ok(())
 }
+
+/// Selects the snapshot to use on a remote.
+///
+/// Arguments:
+/// - `env` is the environment
+/// - `remote` is the remote
+/// - `snapshot` is the ID of the snapshot. If `None`, the latest index of the remote is used
+pub fn snapshot(
+	env: &mut Environment,
+	remote: String,
+	snapshot: Option<String>,
+) -> io::Result<()> {
+	check_snapshot(snapshot.as_deref())?;
+	let mut remotes = Remote::load_list(env)?;
+	let Some(mut r) = remotes.take(remote.as_str()) else {
+		eprintln!("Remote `{remote}` not found");
+		return Ok(());
+	};
+	match &snapshot {
+		Some(id) => println!("Use snapshot `{id}` on remote `{remote}`"),
+		None => println!("Use latest index on remote `{remote}`"),
+	}
+	r.snapshot = snapshot;
+	remotes.insert(r);
+	Remote::save_list(env, remotes.into_iter())?;
+	println!("Run `blimp update` to fetch the index");
+	Ok(())
+}
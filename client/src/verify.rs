@@ -0,0 +1,128 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Verification of installed files against the file manifests of their packages.
+
+use common::{
+	anyhow::{bail, Result},
+	file_manifest::Drift,
+	Environment,
+};
+use std::path::Path;
+
+/// A difference found on an installed file.
+struct Problem<'p> {
+	/// The name of the package owning the file.
+	package: &'p str,
+	/// The path to the file, relative to the system root.
+	path: &'p Path,
+	/// The difference.
+	drift: Drift,
+}
+
+/// Prints the given problem, with the given `kind`.
+///
+/// If `porcelain` is set, the problem is printed as tab-separated fields: the name of the
+/// package, the kind of difference and the path to the file.
+fn print_problem(problem: &Problem, kind: &str, porcelain: bool) {
+	if porcelain {
+		println!("{}\t{kind}\t/{}", problem.package, problem.path.display());
+	} else {
+		println!(
+			"\t{}: /{}: {}",
+			problem.package,
+			problem.path.display(),
+			problem.drift
+		);
+	}
+}
+
+/// Checks the files of the given installed packages against their file manifests.
+///
+/// Arguments:
+/// - `names` is the list of packages to check. If empty, every installed package is checked
+/// - `porcelain` tells whether the output is machine-readable
+/// - `env` is the blimp environment
+///
+/// Configuration files whose content changed are reported separately, as they are expected to
+/// be edited. Other differences make the function fail.
+pub fn verify(names: &[String], porcelain: bool, env: &Environment) -> Result<()> {
+	let installed = env.list_installed()?;
+	let mut packages: Vec<_> = if names.is_empty() {
+		installed.values().collect()
+	} else {
+		let mut failed = false;
+		let packages = names
+			.iter()
+			.filter_map(|name| {
+				let pkg = installed.get(name);
+				if pkg.is_none() {
+					eprintln!("Package `{name}` is not installed!");
+					failed = true;
+				}
+				pkg
+			})
+			.collect();
+		if failed {
+			bail!("cannot find every package");
+		}
+		packages
+	};
+	packages.sort_unstable_by(|a, b| a.desc.name.cmp(&b.desc.name));
+	let check_owner = env.installed_as_root()?;
+	let mut drifts = vec![];
+	let mut modified_configs = vec![];
+	for pkg in packages {
+		if pkg.manifest.is_empty() && !pkg.files.is_empty() {
+			eprintln!(
+				"Package `{}` has no file manifest, reinstall it to verify it",
+				pkg.desc.name
+			);
+			continue;
+		}
+		for file in &pkg.manifest {
+			for drift in file.check(env.sysroot(), check_owner)? {
+				let problem = Problem {
+					package: &pkg.desc.name,
+					path: &file.path,
+					drift,
+				};
+				if file.config && problem.drift == Drift::Content {
+					modified_configs.push(problem);
+				} else {
+					drifts.push(problem);
+				}
+			}
+		}
+	}
+	if !porcelain && !drifts.is_empty() {
+		println!("Changed files:");
+	}
+	for p in &drifts {
+		print_problem(p, p.drift.kind(), porcelain);
+	}
+	if !porcelain && !modified_configs.is_empty() {
+		println!("Modified configuration files:");
+	}
+	for p in &modified_configs {
+		print_problem(p, "modified-config", porcelain);
+	}
+	if !drifts.is_empty() {
+		bail!("{} difference(s) found on installed files", drifts.len());
+	}
+	Ok(())
+}
@@ -0,0 +1,477 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+//! A file manifest lists the files of a package, along with their metadata.
+//!
+//! The manifest is written by the builder into package archives as `manifest.toml`, then stored
+//! in the installed packages database, so that installed files can be checked afterward.
+//!
+//! Example:
+//!
+//! ```toml
+//! [[file]]
+//! path = "usr/bin/foo"
+//! type = "regular"
+//! mode = 0o755
+//! uid = 0
+//! gid = 0
+//! size = 14328
+//! sha256 = "..."
+//!
+//! [[file]]
+//! path = "usr/bin/bar"
+//! type = "symlink"
+//! mode = 0o777
+//! uid = 0
+//! gid = 0
+//! target = "foo"
+//! ```
+
+use crate::util::sha256_file;
+use serde::{Deserialize, Serialize};
+use std::{
+	fmt, fs, io,
+	os::unix::fs::{FileTypeExt, MetadataExt},
+	path::{Path, PathBuf},
+};
+
+/// The path of the file manifest in package archives.
+pub const ARCHIVE_PATH: &str = "manifest.toml";
+/// The suffix of the file to which the new version of a locally modified configuration file is
+/// written on upgrade.
+pub const NEW_SUFFIX: &str = ".blimp-new";
+/// The suffix of the file to which a locally modified configuration file is moved when its
+/// package is removed.
+pub const SAVE_SUFFIX: &str = ".blimp-save";
+
+/// Returns `path` with `suffix` appended to its file name.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+	let mut path = path.as_os_str().to_owned();
+	path.push(suffix);
+	path.into()
+}
+
+/// The type of a file.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileType {
+	/// A regular file.
+	Regular,
+	/// A directory.
+	Directory,
+	/// A symbolic link.
+	Symlink,
+	/// A FIFO.
+	Fifo,
+	/// A character device.
+	CharDevice,
+	/// A block device.
+	BlockDevice,
+	/// A UNIX socket.
+	Socket,
+}
+
+impl From<fs::FileType> for FileType {
+	fn from(file_type: fs::FileType) -> Self {
+		if file_type.is_dir() {
+			Self::Directory
+		} else if file_type.is_symlink() {
+			Self::Symlink
+		} else if file_type.is_fifo() {
+			Self::Fifo
+		} else if file_type.is_char_device() {
+			Self::CharDevice
+		} else if file_type.is_block_device() {
+			Self::BlockDevice
+		} else if file_type.is_socket() {
+			Self::Socket
+		} else {
+			Self::Regular
+		}
+	}
+}
+
+impl fmt::Display for FileType {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			Self::Regular => "regular file",
+			Self::Directory => "directory",
+			Self::Symlink => "symbolic link",
+			Self::Fifo => "FIFO",
+			Self::CharDevice => "character device",
+			Self::BlockDevice => "block device",
+			Self::Socket => "socket",
+		};
+		fmt.write_str(name)
+	}
+}
+
+/// A difference between an installed file and its entry in the file manifest.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Drift {
+	/// The file does not exist.
+	Missing,
+	/// The file has been replaced by a file of another type.
+	Type {
+		expected: FileType,
+		actual: FileType,
+	},
+	/// The content of the file changed.
+	Content,
+	/// The permissions of the file changed.
+	Mode { expected: u32, actual: u32 },
+	/// The owner or the group of the file changed, as `(uid, gid)` pairs.
+	Owner {
+		expected: (u32, u32),
+		actual: (u32, u32),
+	},
+	/// The target of the symbolic link changed.
+	Target { expected: PathBuf, actual: PathBuf },
+}
+
+impl Drift {
+	/// Returns a short name for the kind of difference, for machine-readable outputs.
+	pub fn kind(&self) -> &'static str {
+		match self {
+			Self::Missing => "missing",
+			Self::Type {
+				..
+			} => "type",
+			Self::Content => "content",
+			Self::Mode {
+				..
+			} => "mode",
+			Self::Owner {
+				..
+			} => "owner",
+			Self::Target {
+				..
+			} => "target",
+		}
+	}
+}
+
+impl fmt::Display for Drift {
+	fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Missing => write!(fmt, "missing"),
+			Self::Type {
+				expected,
+				actual,
+			} => write!(fmt, "replaced: expected {expected}, found {actual}"),
+			Self::Content => write!(fmt, "content changed"),
+			Self::Mode {
+				expected,
+				actual,
+			} => write!(
+				fmt,
+				"mode changed: expected {expected:04o}, found {actual:04o}"
+			),
+			Self::Owner {
+				expected: (expected_uid, expected_gid),
+				actual: (actual_uid, actual_gid),
+			} => write!(
+				fmt,
+				"owner changed: expected {expected_uid}:{expected_gid}, found {actual_uid}:{actual_gid}"
+			),
+			Self::Target {
+				expected,
+				actual,
+			} => write!(
+				fmt,
+				"symbolic link target changed: expected `{}`, found `{}`",
+				expected.display(),
+				actual.display()
+			),
+		}
+	}
+}
+
+/// A file of a package.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct FileEntry {
+	/// The path to the file, relative to the system root.
+	pub path: PathBuf,
+	/// The type of the file.
+	#[serde(rename = "type")]
+	pub file_type: FileType,
+	/// The permissions of the file, including the setuid, setgid and sticky bits.
+	pub mode: u32,
+	/// The ID of the owner of the file.
+	pub uid: u32,
+	/// The ID of the group of the file.
+	pub gid: u32,
+	/// The size of the file in bytes, if it is a regular file.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub size: Option<u64>,
+	/// The SHA-256 hash of the content of the file in hexadecimal, if it is a regular file.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sha256: Option<String>,
+	/// The target of the file, if it is a symbolic link.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub target: Option<PathBuf>,
+	/// Tells whether the file is a configuration file, which users are expected to edit.
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub config: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+	!b
+}
+
+impl FileEntry {
+	/// Reads the metadata of the file at `path`, recorded under the path `name`.
+	///
+	/// Symbolic links are not followed.
+	pub fn read(path: &Path, name: PathBuf) -> io::Result<Self> {
+		let meta = fs::symlink_metadata(path)?;
+		let file_type = FileType::from(meta.file_type());
+		let (size, sha256) = if file_type == FileType::Regular {
+			(Some(meta.len()), Some(sha256_file(path)?))
+		} else {
+			(None, None)
+		};
+		let target = if file_type == FileType::Symlink {
+			Some(fs::read_link(path)?)
+		} else {
+			None
+		};
+		Ok(Self {
+			path: name,
+			file_type,
+			mode: meta.mode() & 0o7777,
+			uid: meta.uid(),
+			gid: meta.gid(),
+			size,
+			sha256,
+			target,
+			config: false,
+		})
+	}
+
+	/// Tells whether the content of the file installed in the system at `sysroot` differs from
+	/// the entry's, or if the file has been replaced by a file of another type.
+	///
+	/// A missing file is not considered different.
+	pub fn content_differs(&self, sysroot: &Path) -> io::Result<bool> {
+		let drifts = self.check(sysroot, false)?;
+		Ok(drifts
+			.iter()
+			.any(|d| matches!(d, Drift::Content | Drift::Type { .. })))
+	}
+
+	/// Compares the file installed in the system at `sysroot` with the entry.
+	///
+	/// If the file is missing or has been replaced by a file of another type, this is the only
+	/// difference returned.
+	///
+	/// `check_owner` tells whether the owner of the file is compared. Files are owned by the user
+	/// who installed them, so that their owner matches the entry only if they were installed as
+	/// root.
+	pub fn check(&self, sysroot: &Path, check_owner: bool) -> io::Result<Vec<Drift>> {
+		let path = sysroot.join(&self.path);
+		let mut meta = match fs::symlink_metadata(&path) {
+			Ok(meta) => meta,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![Drift::Missing]),
+			Err(e) => return Err(e),
+		};
+		// Directories may already exist on the system as symbolic links (e.g. `/lib` ->
+		// `/usr/lib`), in which case installation goes through them
+		if self.file_type == FileType::Directory && meta.is_symlink() {
+			if let Ok(target_meta) = fs::metadata(&path) {
+				meta = target_meta;
+			}
+		}
+		let file_type = FileType::from(meta.file_type());
+		if file_type != self.file_type {
+			return Ok(vec![Drift::Type {
+				expected: self.file_type,
+				actual: file_type,
+			}]);
+		}
+		let mut drifts = vec![];
+		match file_type {
+			FileType::Regular => {
+				if let Some(sha256) = &self.sha256 {
+					let changed = self.size.is_some_and(|size| size != meta.len())
+						|| sha256_file(&path)? != *sha256;
+					if changed {
+						drifts.push(Drift::Content);
+					}
+				}
+			}
+			FileType::Symlink => {
+				let actual = fs::read_link(&path)?;
+				if let Some(expected) = self.target.as_ref().filter(|t| **t != actual) {
+					drifts.push(Drift::Target {
+						expected: expected.clone(),
+						actual,
+					});
+				}
+			}
+			_ => {}
+		}
+		// The permissions of symbolic links are not meaningful
+		let mode = meta.mode() & 0o7777;
+		if file_type != FileType::Symlink && mode != self.mode {
+			drifts.push(Drift::Mode {
+				expected: self.mode,
+				actual: mode,
+			});
+		}
+		let owner = (meta.uid(), meta.gid());
+		if check_owner && owner != (self.uid, self.gid) {
+			drifts.push(Drift::Owner {
+				expected: (self.uid, self.gid),
+				actual: owner,
+			});
+		}
+		Ok(drifts)
+	}
+}
+
+/// The list of files of a package.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FileManifest {
+	/// The files, sorted by path.
+	#[serde(default)]
+	pub file: Vec<FileEntry>,
+}
+
+impl FileManifest {
+	/// Lists the files under the directory `root`, recursively.
+	///
+	/// Paths are recorded relative to `root`, which is itself excluded.
+	pub fn from_dir(root: &Path) -> io::Result<Self> {
+		fn walk(root: &Path, dir: &Path, files: &mut Vec<FileEntry>) -> io::Result<()> {
+			let mut entries = fs::read_dir(root.join(dir))?
+				.map(|e| e.map(|e| e.file_name()))
+				.collect::<io::Result<Vec<_>>>()?;
+			entries.sort_unstable();
+			for name in entries {
+				let name = dir.join(name);
+				let entry = FileEntry::read(&root.join(&name), name.clone())?;
+				let is_dir = entry.file_type == FileType::Directory;
+				files.push(entry);
+				if is_dir {
+					walk(root, &name, files)?;
+				}
+			}
+			Ok(())
+		}
+		let mut file = vec![];
+		walk(root, Path::new(""), &mut file)?;
+		Ok(Self {
+			file,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util::create_tmp_dir;
+	use std::{
+		env,
+		os::{unix, unix::fs::PermissionsExt},
+	};
+
+	#[test]
+	fn from_dir() {
+		let root = create_tmp_dir(env::temp_dir()).unwrap();
+		fs::create_dir(root.join("bin")).unwrap();
+		fs::write(root.join("bin/foo"), b"foo").unwrap();
+		unix::fs::symlink("foo", root.join("bin/bar")).unwrap();
+		let manifest = FileManifest::from_dir(&root).unwrap();
+		fs::remove_dir_all(&root).unwrap();
+
+		let paths: Vec<_> = manifest.file.iter().map(|f| f.path.as_path()).collect();
+		assert_eq!(
+			paths,
+			[Path::new("bin"), Path::new("bin/bar"), Path::new("bin/foo")]
+		);
+		let [dir, link, file] = &manifest.file[..] else {
+			unreachable!();
+		};
+		assert_eq!(dir.file_type, FileType::Directory);
+		assert_eq!(link.file_type, FileType::Symlink);
+		assert_eq!(link.target.as_deref(), Some(Path::new("foo")));
+		assert_eq!(file.file_type, FileType::Regular);
+		assert_eq!(file.size, Some(3));
+		assert_eq!(
+			file.sha256.as_deref(),
+			Some("2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae")
+		);
+		let content = toml::to_string(&manifest).unwrap();
+		let parsed: FileManifest = toml::from_str(&content).unwrap();
+		assert_eq!(parsed.file, manifest.file);
+	}
+
+	#[test]
+	fn check() {
+		let root = create_tmp_dir(env::temp_dir()).unwrap();
+		fs::write(root.join("foo"), b"foo").unwrap();
+		fs::write(root.join("bar"), b"bar").unwrap();
+		unix::fs::symlink("foo", root.join("link")).unwrap();
+		let manifest = FileManifest::from_dir(&root).unwrap();
+		let clean = manifest
+			.file
+			.iter()
+			.all(|f| f.check(&root, true).unwrap().is_empty());
+		assert!(clean);
+
+		fs::write(root.join("foo"), b"baz").unwrap();
+		fs::set_permissions(root.join("foo"), fs::Permissions::from_mode(0o4700)).unwrap();
+		fs::remove_file(root.join("bar")).unwrap();
+		fs::remove_file(root.join("link")).unwrap();
+		unix::fs::symlink("bar", root.join("link")).unwrap();
+		let drifts: Vec<_> = manifest
+			.file
+			.iter()
+			.map(|f| f.check(&root, true).unwrap())
+			.collect();
+		fs::remove_dir_all(&root).unwrap();
+
+		// Files are sorted: `bar`, `foo`, `link`
+		assert_eq!(drifts[0], [Drift::Missing]);
+		assert_eq!(drifts[1][0], Drift::Content);
+		assert_eq!(drifts[1][1].kind(), "mode");
+		assert_eq!(
+			drifts[2],
+			[Drift::Target {
+				expected: "foo".into(),
+				actual: "bar".into(),
+			}]
+		);
+	}
+
+	#[test]
+	fn check_owner() {
+		let root = create_tmp_dir(env::temp_dir()).unwrap();
+		fs::write(root.join("foo"), b"foo").unwrap();
+		let mut entry = FileEntry::read(&root.join("foo"), "foo".into()).unwrap();
+		// Installed by another user than the one recorded in the manifest
+		entry.uid += 1;
+		let drifts = entry.check(&root, true).unwrap();
+		let unchecked = entry.check(&root, false).unwrap();
+		fs::remove_dir_all(&root).unwrap();
+
+		assert_eq!(drifts.len(), 1);
+		assert_eq!(drifts[0].kind(), "owner");
+		assert!(unchecked.is_empty());
+	}
+}
@@ -24,10 +24,15 @@ pub use tar;
 pub use tokio;
 pub use tokio_util;
 pub use utils as maestro_utils;
+pub use xz2;
+pub use zstd;
 
 #[cfg(feature = "network")]
 pub mod download;
+pub mod file_manifest;
 pub mod lock;
+pub mod lockfile;
+pub mod manifest;
 pub mod package;
 pub mod repository;
 pub mod util;
@@ -38,12 +43,15 @@ use crate::{
 	version::Version,
 };
 use anyhow::{bail, Result};
+use file_manifest::{FileEntry, FileManifest};
 use package::{InstalledPackage, Package};
 use std::{
+	collections::{HashMap, HashSet},
 	env,
 	error::Error,
 	fs,
-	io::{self, ErrorKind},
+	io::{self, ErrorKind, Read},
+	os::unix::fs::MetadataExt,
 	path::{Path, PathBuf},
 };
 
@@ -59,6 +67,14 @@ const REMOTES: &str = "var/lib/blimp/remotes";
 /// The user agent for HTTP requests.
 pub const USER_AGENT: &str = concat!("blimp/", env!("CARGO_PKG_VERSION"));
 
+/// Returns the list of local repositories from the `LOCAL_REPO` environment variable, which
+/// contains a colon-separated list of paths.
+fn local_repos_from_env() -> Vec<PathBuf> {
+	env::var("LOCAL_REPO") // TODO var_os
+		.map(|s| s.split(':').map(PathBuf::from).collect())
+		.unwrap_or_default()
+}
+
 /// An environment is a system managed by the package manager.
 ///
 /// On creation, the environment creates a lockfile to ensure no other instance can access it at
@@ -72,6 +88,8 @@ pub struct Environment {
 	local_repos: Vec<PathBuf>,
 	/// The architecture to install for
 	arch: String,
+	/// Tells whether the environment is locked by the instance.
+	locked: bool,
 }
 
 impl Environment {
@@ -91,16 +109,35 @@ impl Environment {
 		let sysroot = sysroot.canonicalize()?;
 		let path = sysroot.join(LOCK_PATH);
 		let acquired = lock::lock(&path)?;
-		let local_repos = env::var("LOCAL_REPO") // TODO var_os
-			.map(|s| s.split(':').map(PathBuf::from).collect())
-			.unwrap_or_default();
 		Ok(acquired.then(|| Self {
 			sysroot,
-			local_repos,
+			local_repos: local_repos_from_env(),
 			arch: arch.to_owned(),
+			locked: true,
 		}))
 	}
 
+	/// Opens the environment at `sysroot` without locking it, so that it can be read without
+	/// write access to it.
+	///
+	/// Another instance may modify the environment at the same time: it must not be modified
+	/// through the returned instance.
+	///
+	/// The arguments are the same as for [`Self::acquire`].
+	pub fn open_read_only(sysroot: &Path, arch: &str) -> io::Result<Self> {
+		Ok(Self {
+			sysroot: sysroot.canonicalize()?,
+			local_repos: local_repos_from_env(),
+			arch: arch.to_owned(),
+			locked: false,
+		})
+	}
+
+	/// Returns the path to the lock file of the environment at `sysroot`.
+	pub fn lock_path(sysroot: &Path) -> PathBuf {
+		sysroot.join(LOCK_PATH)
+	}
+
 	/// Returns the sysroot of the current environment.
 	#[inline]
 	pub fn sysroot(&self) -> &Path {
@@ -137,12 +174,24 @@ impl Environment {
 		Ok(repos)
 	}
 
-	/// If installed, returns the version of the package with the given `name`
-	pub fn get_installed_version(&self, name: &str) -> Result<Option<Version>> {
+	/// Tells whether packages are installed as root in the environment.
+	///
+	/// Installed files are owned by the user who installed them. Package archives record them as
+	/// owned by root, so that owners can be verified only if packages are installed as root.
+	pub fn installed_as_root(&self) -> io::Result<bool> {
+		match fs::metadata(self.sysroot.join(INSTALLED_DB)) {
+			Ok(meta) => Ok(meta.uid() == 0),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(true),
+			Err(e) => Err(e),
+		}
+	}
+
+	/// If installed, returns the package with the given `name`
+	pub fn get_installed(&self, name: &str) -> Result<Option<InstalledPackage>> {
 		// Ensure the parent directory exists
 		let path = self.sysroot.join(INSTALLED_DB);
 		fs::create_dir_all(&path)?;
-		// Read file and get version
+		// Read file
 		let path = path.join(name);
 		let res = fs::read_to_string(path);
 		let installed = match res {
@@ -150,8 +199,66 @@ impl Environment {
 			Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
 			Err(e) => return Err(e.into()),
 		};
-		let installed: InstalledPackage = toml::from_str(&installed)?;
-		Ok(Some(installed.desc.version))
+		Ok(Some(toml::from_str(&installed)?))
+	}
+
+	/// If installed, returns the version of the package with the given `name`
+	pub fn get_installed_version(&self, name: &str) -> Result<Option<Version>> {
+		Ok(self.get_installed(name)?.map(|i| i.desc.version))
+	}
+
+	/// Returns all installed packages, by name
+	pub fn list_installed(&self) -> Result<HashMap<String, InstalledPackage>> {
+		// Ensure the parent directory exists
+		let path = self.sysroot.join(INSTALLED_DB);
+		fs::create_dir_all(&path)?;
+		let mut installed = HashMap::new();
+		for ent in fs::read_dir(path)? {
+			let ent = ent?;
+			let content = fs::read_to_string(ent.path())?;
+			let pkg: InstalledPackage = toml::from_str(&content)?;
+			installed.insert(pkg.desc.name.clone(), pkg);
+		}
+		Ok(installed)
+	}
+
+	/// Returns the configuration files waiting to be merged with their new version, as pairs of
+	/// the name of the package owning the file and the path to the file, relative to the sysroot.
+	///
+	/// When a package is upgraded, locally modified configuration files are kept, and their new
+	/// version is written next to them, with the [`file_manifest::NEW_SUFFIX`] suffix.
+	///
+	/// Files are sorted by package name, then in the order of the package's manifest.
+	pub fn pending_configs(&self) -> Result<Vec<(String, PathBuf)>> {
+		let installed = self.list_installed()?;
+		let mut packages: Vec<_> = installed.into_values().collect();
+		packages.sort_unstable_by(|a, b| a.desc.name.cmp(&b.desc.name));
+		let pending = packages
+			.into_iter()
+			.flat_map(|pkg| {
+				let name = pkg.desc.name;
+				pkg.manifest
+					.into_iter()
+					.filter(|f| f.config)
+					.filter(|f| {
+						let path = self.sysroot.join(&f.path);
+						file_manifest::with_suffix(&path, file_manifest::NEW_SUFFIX).exists()
+					})
+					.map(move |f| (name.clone(), f.path))
+			})
+			.collect();
+		Ok(pending)
+	}
+
+	/// Sets whether the installed package with the given `name` has been installed explicitly.
+	///
+	/// If the package is not installed, the function does nothing.
+	pub fn set_explicit(&self, name: &str, explicit: bool) -> Result<()> {
+		let Some(mut pkg) = self.get_installed(name)? else {
+			return Ok(());
+		};
+		pkg.explicit = explicit;
+		self.write_installed_version(&pkg)
 	}
 
 	/// Writes installed package information
@@ -170,26 +277,54 @@ impl Environment {
 	///
 	/// Arguments:
 	/// - `pkg` is the package to be installed
-	/// - `archive_path` is the path to the archive of the package
+	/// - `repo` is the repository containing the archive of the package
+	/// - `explicit` tells whether the package is installed explicitly, as opposed to being
+	///   installed as a dependency. A package that was already installed explicitly remains so
 	///
 	/// The function does not resolve dependencies. It is the caller's responsibility to install
 	/// them beforehand.
-	pub fn install(&mut self, pkg: &Package, archive_path: &Path) -> Result<(), Box<dyn Error>> {
+	pub fn install(
+		&mut self,
+		pkg: &Package,
+		repo: &Repository,
+		explicit: bool,
+	) -> Result<(), Box<dyn Error>> {
+		let installed = self.get_installed(&pkg.name)?;
+		let explicit = explicit || installed.as_ref().is_some_and(|i| i.explicit);
+		// The files of the previous version of the package, to detect modified configuration files
+		let previous: HashMap<&Path, &FileEntry> = installed
+			.iter()
+			.flat_map(|i| &i.manifest)
+			.map(|f| (f.path.as_path(), f))
+			.collect();
+		let archive_path = repo.get_archive_path(self.arch(), pkg);
+		let sha256 = util::sha256_file(&archive_path)?;
 		// Read archive
-		let mut archive = util::read_package_archive(archive_path)?;
+		let mut archive = util::read_package_archive(&archive_path)?;
 		// TODO Get hooks (pre-install-hook and post-install-hook)
 		// TODO Execute pre-install-hook
 		// The list of installed files
 		let mut files = vec![];
+		// The list of files in the archive, including the ones that are not installed
+		let mut archived = HashSet::new();
+		let mut manifest = None;
 		// Copy files
 		for e in archive.entries()? {
 			let mut e = e?;
 			let path = e.path()?;
+			if path == Path::new(file_manifest::ARCHIVE_PATH) {
+				let mut content = String::new();
+				e.read_to_string(&mut content)?;
+				let m: FileManifest = toml::from_str(&content)?;
+				manifest = Some(m.file);
+				continue;
+			}
 			// Exclude files outside the `data` directory
 			let Ok(path) = path.strip_prefix("data/") else {
 				continue;
 			};
 			let dst = self.sysroot.join(path);
+			archived.insert(path.to_path_buf());
 			// Skip directory entries whose target already exists. Required because the
 			// FHS layout pre-creates symlinks (e.g. `/lib` -> `/usr/lib`) that would
 			// otherwise collide with directory entries from the archive.
@@ -201,30 +336,118 @@ impl Environment {
 				fs::create_dir_all(parent)?;
 			}
 			let path = path.to_path_buf();
+			// Keep locally modified configuration files, unless they already match the new version
+			let config = manifest
+				.iter()
+				.flatten()
+				.find(|f| f.config && f.path == path);
+			if let Some(config) = config {
+				let modified = config.content_differs(&self.sysroot)?
+					&& previous
+						.get(path.as_path())
+						.map(|f| f.content_differs(&self.sysroot))
+						.transpose()?
+						.unwrap_or(true);
+				if modified {
+					let new = file_manifest::with_suffix(&dst, file_manifest::NEW_SUFFIX);
+					println!(
+						"Keeping modified configuration file `/{}`, the new version is written to `/{}{}`",
+						path.display(),
+						path.display(),
+						file_manifest::NEW_SUFFIX
+					);
+					e.unpack(new)?;
+					files.push(path);
+					continue;
+				}
+			}
 			e.unpack(dst)?;
 			files.push(path);
 		}
+		// Remove the files of the previous version that are not part of the new one
+		if let Some(installed) = &installed {
+			let stale: Vec<_> = installed
+				.files
+				.iter()
+				.filter(|path| !archived.contains(*path))
+				.cloned()
+				.collect();
+			self.remove_files(stale, &installed.manifest)?;
+		}
+		// Archives built without a file manifest: record the files as they have been installed
+		let manifest = match manifest {
+			Some(manifest) => manifest,
+			None => files
+				.iter()
+				.filter(|path| !path.as_os_str().is_empty())
+				.map(|path| FileEntry::read(&self.sysroot.join(path), path.clone()))
+				.collect::<io::Result<_>>()?,
+		};
 		// TODO Execute post-install-hook
 		self.write_installed_version(&InstalledPackage {
 			desc: pkg.clone(),
 			files,
+			manifest,
+			repository: Some(repo.name()),
+			sha256: Some(sha256),
+			explicit,
 		})?;
 		Ok(())
 	}
 
 	/// Install all packages into environment.
 	///
+	/// Installed packages replaced by one of the new packages are removed first. A package
+	/// replacing an explicitly installed package is itself considered explicit.
+	///
+	/// If one of the new packages conflicts with an installed package that it does not replace,
+	/// the function fails without modifying the environment.
+	///
 	/// Arguments:
 	/// - `total_packages` is the whole list of packages to install
+	/// - `explicit` is the list of names of the packages installed explicitly. Other packages are
+	///   considered to be installed as dependencies
 	pub fn install_packages<'r>(
 		&mut self,
 		total_packages: &PackagesWithRepositoryVec<'r>,
+		explicit: &[String],
 	) -> Result<()> {
+		let mut explicit = explicit.to_vec();
+		let installed = self.list_installed()?;
+		let mut replaced = vec![];
+		for inst in installed.values() {
+			// Reinstalling or upgrading a package is not a replacement
+			if total_packages.iter().any(|(p, _)| p.name == inst.desc.name) {
+				continue;
+			}
+			if let Some((pkg, _)) = total_packages.iter().find(|(p, _)| p.replaces(&inst.desc)) {
+				if inst.explicit {
+					explicit.push(pkg.name.clone());
+				}
+				replaced.push((inst, pkg));
+			} else if let Some((pkg, _)) = total_packages
+				.iter()
+				.find(|(p, _)| p.conflicts_with(&inst.desc))
+			{
+				bail!(
+					"package `{}` conflicts with installed package `{}`",
+					pkg.name,
+					inst.desc.name
+				);
+			}
+		}
+		for (inst, pkg) in replaced {
+			println!(
+				"Removing `{}` (replaced by `{}`)...",
+				inst.desc.name, pkg.name
+			);
+			self.remove(inst)?;
+		}
 		let mut failed = false;
 		for (pkg, repo) in total_packages {
 			println!("Installing `{}`...", pkg.name);
-			let archive_path = repo.get_archive_path(self.arch(), &pkg.name, &pkg.version);
-			if let Err(e) = self.install(pkg, &archive_path) {
+			let is_explicit = explicit.contains(&pkg.name);
+			if let Err(e) = self.install(pkg, repo, is_explicit) {
 				eprintln!("Failed to install `{}`: {e}", &pkg.name);
 				failed = true;
 			}
@@ -253,17 +476,55 @@ impl Environment {
 
 	/// Removes the given package.
 	///
+	/// Locally modified configuration files are not removed, but moved to a file with the
+	/// [`file_manifest::SAVE_SUFFIX`] suffix.
+	///
 	/// This function does not check dependency breakage. It is the caller's responsibility to
 	/// ensure no other package depend on the package to be removed.
 	pub fn remove(&mut self, pkg: &InstalledPackage) -> Result<()> {
 		// TODO Get hooks (pre-remove-hook and post-remove-hook. Copy at installation?)
 		// TODO Execute pre-remove-hook
 		// Remove the package's files
+		self.remove_files(pkg.files.clone(), &pkg.manifest)?;
+		// TODO Execute post-remove-hook
+		let path = self.sysroot.join(INSTALLED_DB).join(&pkg.desc.name);
+		match fs::remove_file(path) {
+			Ok(_) => Ok(()),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	/// Removes the given installed `files`, whose metadata are listed in `manifest`.
+	///
+	/// Locally modified configuration files are not removed, but moved to a file with the
+	/// [`file_manifest::SAVE_SUFFIX`] suffix.
+	fn remove_files(&self, mut files: Vec<PathBuf>, manifest: &[FileEntry]) -> Result<()> {
 		// Removing is made in reverse order to ensure inner files are removed first
-		let mut files = pkg.files.clone();
 		files.sort_unstable_by(|a, b| a.cmp(b).reverse());
 		for sys_path in &files {
 			let path = util::concat_paths(&self.sysroot, sys_path);
+			let config = manifest.iter().find(|f| f.config && f.path == *sys_path);
+			if let Some(config) = config {
+				// A pending new version of the file belongs to the package
+				let new = file_manifest::with_suffix(&path, file_manifest::NEW_SUFFIX);
+				match fs::remove_file(new) {
+					Ok(_) => {}
+					Err(e) if e.kind() == ErrorKind::NotFound => {}
+					Err(e) => return Err(e.into()),
+				}
+				if config.content_differs(&self.sysroot)? {
+					let save = file_manifest::with_suffix(&path, file_manifest::SAVE_SUFFIX);
+					println!(
+						"Keeping modified configuration file `/{}` as `/{}{}`",
+						sys_path.display(),
+						sys_path.display(),
+						file_manifest::SAVE_SUFFIX
+					);
+					fs::rename(&path, save)?;
+					continue;
+				}
+			}
 			let dir = fs::metadata(&path)
 				.map(|m| m.file_type().is_dir())
 				.unwrap_or(false);
@@ -279,15 +540,102 @@ impl Environment {
 				Err(e) => return Err(e.into()),
 			}
 		}
-		// TODO Execute post-remove-hook
-		// TODO remove package from installed db
 		Ok(())
 	}
 }
 
 impl Drop for Environment {
 	fn drop(&mut self) {
+		if !self.locked {
+			return;
+		}
 		let path = self.sysroot.join(LOCK_PATH);
 		lock::unlock(&path).unwrap_or_else(|e| eprintln!("blimp: could not remove lockfile: {e}"));
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use flate2::{write::GzEncoder, Compression};
+	use std::fs::File;
+
+	const ARCH: &str = "x86_64";
+
+	/// Writes an archive of the package `foo` at `version` in the repository at `repo`, with
+	/// the configuration file `etc/foo.conf` containing `conf`.
+	fn write_package(repo: &Path, version: &str, conf: &str) -> Package {
+		let pkg: Package = toml::from_str(&format!(
+			"name = \"foo\"\nversion = \"{version}\"\ndescription = \"\""
+		))
+		.unwrap();
+		let data = util::create_tmp_dir(env::temp_dir()).unwrap();
+		fs::create_dir(data.join("etc")).unwrap();
+		fs::write(data.join("etc/foo.conf"), conf).unwrap();
+		let mut manifest = FileManifest::from_dir(&data).unwrap();
+		for file in &mut manifest.file {
+			file.config = file.path == Path::new("etc/foo.conf");
+		}
+		let manifest = toml::to_string(&manifest).unwrap();
+		let path = Repository::local(repo.to_path_buf()).get_archive_path(ARCH, &pkg);
+		fs::create_dir_all(path.parent().unwrap()).unwrap();
+		let enc = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+		let mut tar = tar::Builder::new(enc);
+		let mut header = tar::Header::new_gnu();
+		header.set_size(manifest.len() as u64);
+		header.set_mode(0o644);
+		tar.append_data(
+			&mut header,
+			file_manifest::ARCHIVE_PATH,
+			manifest.as_bytes(),
+		)
+		.unwrap();
+		tar.append_dir_all("data", &data).unwrap();
+		tar.into_inner().unwrap().finish().unwrap();
+		fs::remove_dir_all(data).unwrap();
+		pkg
+	}
+
+	#[test]
+	fn config_files() {
+		let root = util::create_tmp_dir(env::temp_dir()).unwrap();
+		let repo_path = root.join("repo");
+		let sysroot = root.join("sysroot");
+		fs::create_dir(&sysroot).unwrap();
+		let repo = Repository::local(repo_path.clone());
+		let mut env = Environment::acquire(&sysroot, ARCH).unwrap().unwrap();
+		let conf = env.sysroot().join("etc/foo.conf");
+		let new = file_manifest::with_suffix(&conf, file_manifest::NEW_SUFFIX);
+		let save = file_manifest::with_suffix(&conf, file_manifest::SAVE_SUFFIX);
+
+		let pkg = write_package(&repo_path, "1.0", "a = 1\n");
+		env.install(&pkg, &repo, true).unwrap();
+		assert_eq!(fs::read_to_string(&conf).unwrap(), "a = 1\n");
+		assert!(env.pending_configs().unwrap().is_empty());
+		// A configuration file that has not been modified is upgraded
+		let pkg = write_package(&repo_path, "1.1", "a = 2\n");
+		env.install(&pkg, &repo, false).unwrap();
+		assert_eq!(fs::read_to_string(&conf).unwrap(), "a = 2\n");
+		assert!(!new.exists());
+		// A modified configuration file is kept
+		fs::write(&conf, "a = local\n").unwrap();
+		let pkg = write_package(&repo_path, "1.2", "a = 3\n");
+		env.install(&pkg, &repo, false).unwrap();
+		assert_eq!(fs::read_to_string(&conf).unwrap(), "a = local\n");
+		assert_eq!(fs::read_to_string(&new).unwrap(), "a = 3\n");
+		assert_eq!(
+			env.pending_configs().unwrap(),
+			[("foo".to_owned(), PathBuf::from("etc/foo.conf"))]
+		);
+		let inst = env.get_installed("foo").unwrap().unwrap();
+		assert!(inst.explicit);
+		// On removal, the modified configuration file is saved
+		env.remove(&inst).unwrap();
+		assert!(!conf.exists());
+		assert!(!new.exists());
+		assert_eq!(fs::read_to_string(&save).unwrap(), "a = local\n");
+
+		drop(env);
+		fs::remove_dir_all(root).unwrap();
+	}
+}
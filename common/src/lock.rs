@@ -25,19 +25,20 @@ use std::{fs, fs::OpenOptions, io, path::Path};
 ///
 /// `path` is the path to the file.
 ///
-/// If the file was successfully created, the function returns `true`. Else, it returns `false`.
+/// If the file was successfully created, the function returns `true`. If it already exists, the
+/// function returns `false`.
 pub fn lock(path: &Path) -> io::Result<bool> {
 	if let Some(parent) = path.parent() {
 		fs::create_dir_all(parent)?;
 	}
 	// Try to create the file and failing if it already exists, preventing TOCTOU race
 	// conditions
-	let acquired = OpenOptions::new()
-		.write(true)
-		.create_new(true)
-		.open(path)
-		.is_ok();
-	Ok(acquired)
+	let res = OpenOptions::new().write(true).create_new(true).open(path);
+	match res {
+		Ok(_) => Ok(true),
+		Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+		Err(e) => Err(e),
+	}
 }
 
 /// Removes the instance lock file.
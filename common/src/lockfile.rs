@@ -0,0 +1,164 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A lockfile records the exact set of packages installed on a system, so that it can be
+//! reproduced on another system.
+//!
+//! This is not to be confused with the instance lock file, see [`crate::lock`].
+
+use crate::{package::InstalledPackage, version::Version, Environment};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// A package entry in a lockfile.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct LockedPackage {
+	/// The package's name.
+	pub name: String,
+	/// The exact version of the package.
+	pub version: Version,
+	/// The name of the repository the package was installed from, if known.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub repository: Option<String>,
+	/// The SHA-256 checksum of the package's archive, in hexadecimal, if known.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sha256: Option<String>,
+	/// Tells whether the package has been installed explicitly.
+	#[serde(default, skip_serializing_if = "is_false")]
+	pub explicit: bool,
+}
+
+fn is_false(b: &bool) -> bool {
+	!b
+}
+
+impl From<&InstalledPackage> for LockedPackage {
+	fn from(pkg: &InstalledPackage) -> Self {
+		Self {
+			name: pkg.desc.name.clone(),
+			version: pkg.desc.version.clone(),
+			repository: pkg.repository.clone(),
+			sha256: pkg.sha256.clone(),
+			explicit: pkg.explicit,
+		}
+	}
+}
+
+/// The content of a lockfile.
+#[derive(Deserialize, Serialize)]
+pub struct Lockfile {
+	/// The architecture of the packages.
+	pub arch: String,
+	/// The list of packages, sorted by name.
+	#[serde(default)]
+	pub package: Vec<LockedPackage>,
+}
+
+impl Lockfile {
+	/// Reads the lockfile at `path`.
+	pub fn load(path: &Path) -> Result<Self> {
+		let content = fs::read_to_string(path)
+			.map_err(|e| anyhow!("cannot read `{}`: {e}", path.display()))?;
+		toml::from_str(&content).map_err(|e| anyhow!("invalid lockfile `{}`: {e}", path.display()))
+	}
+
+	/// Serializes the lockfile.
+	pub fn to_toml(&self) -> Result<String> {
+		Ok(toml::to_string(self)?)
+	}
+
+	/// Creates a lockfile from the packages installed in the given environment.
+	pub fn from_env(env: &Environment) -> Result<Self> {
+		let mut package: Vec<LockedPackage> = env
+			.list_installed()?
+			.values()
+			.map(LockedPackage::from)
+			.collect();
+		package.sort_unstable_by(|p0, p1| p0.name.cmp(&p1.name));
+		Ok(Self {
+			arch: env.arch().to_owned(),
+			package,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util;
+	use std::env;
+
+	#[test]
+	fn round_trip() {
+		let sysroot = util::create_tmp_dir(env::temp_dir()).unwrap();
+		let env = Environment::acquire(&sysroot, "x86_64").unwrap().unwrap();
+		for (name, version, repository, explicit) in [
+			("foo", "1.0", Some("main"), true),
+			("bar", "2.1-rc1", None, false),
+		] {
+			let desc = toml::from_str(&format!(
+				"name = \"{name}\"\nversion = \"{version}\"\ndescription = \"\""
+			))
+			.unwrap();
+			env.write_installed_version(&InstalledPackage {
+				desc,
+				files: vec![],
+				manifest: vec![],
+				repository: repository.map(str::to_owned),
+				sha256: Some(format!("{name}-sha256")),
+				explicit,
+			})
+			.unwrap();
+		}
+		let exported = Lockfile::from_env(&env).unwrap();
+		let path = sysroot.join("blimp.lock");
+		fs::write(&path, exported.to_toml().unwrap()).unwrap();
+		let imported = Lockfile::load(&path).unwrap();
+		drop(env);
+		fs::remove_dir_all(sysroot).unwrap();
+
+		assert_eq!(imported.arch, "x86_64");
+		let packages: Vec<_> = imported
+			.package
+			.iter()
+			.map(|p| {
+				(
+					p.name.as_str(),
+					p.version.to_string(),
+					p.repository.as_deref(),
+					p.sha256.as_deref(),
+					p.explicit,
+				)
+			})
+			.collect();
+		assert_eq!(
+			packages,
+			[
+				("bar", "2.1-rc1".to_owned(), None, Some("bar-sha256"), false),
+				(
+					"foo",
+					"1.0".to_owned(),
+					Some("main"),
+					Some("foo-sha256"),
+					true
+				),
+			]
+		);
+	}
+}
@@ -0,0 +1,66 @@
+/*
+ * Copyright 2025 Luc Lenôtre
+ *
+ * This file is part of Maestro.
+ *
+ * Maestro is free software: you can redistribute it and/or modify it under the
+ * terms of the GNU General Public License as published by the Free Software
+ * Foundation, either version 3 of the License, or (at your option) any later
+ * version.
+ *
+ * Maestro is distributed in the hope that it will be useful, but WITHOUT ANY
+ * WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR
+ * A PARTICULAR PURPOSE. See the GNU General Public License for more details.
+ *
+ * You should have received a copy of the GNU General Public License along with
+ * Maestro. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A manifest describes the desired state of a system, as a list of wanted packages.
+//!
+//! Example:
+//!
+//! ```toml
+//! [[package]]
+//! name = "bash"
+//!
+//! [[package]]
+//! name = "gcc"
+//! version = ">=15.0"
+//! ```
+
+use crate::version::VersionConstraint;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// A package wanted on the system.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct WantedPackage {
+	/// The package's name.
+	pub name: String,
+	/// The constraint the installed version must match.
+	#[serde(default = "any_version", rename = "version")]
+	pub version_constraint: VersionConstraint,
+}
+
+fn any_version() -> VersionConstraint {
+	VersionConstraint::Any
+}
+
+/// The content of a manifest.
+#[derive(Deserialize, Serialize)]
+pub struct Manifest {
+	/// The list of wanted packages.
+	#[serde(default)]
+	pub package: Vec<WantedPackage>,
+}
+
+impl Manifest {
+	/// Reads the manifest at `path`.
+	pub fn load(path: &Path) -> Result<Self> {
+		let content = fs::read_to_string(path)
+			.map_err(|e| anyhow!("cannot read `{}`: {e}", path.display()))?;
+		toml::from_str(&content).map_err(|e| anyhow!("invalid manifest `{}`: {e}", path.display()))
+	}
+}
@@ -21,16 +21,18 @@
 //! Packages are usually downloaded from a remote host.
 
 use crate::{
+	file_manifest::FileEntry,
 	repository::Repository,
 	version::{Version, VersionConstraint},
 };
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
 use std::{
 	collections::HashMap,
 	fmt, fs,
 	io::{self, ErrorKind},
 	path::{Path, PathBuf},
+	str::FromStr,
 };
 
 /// Tells whether the given package name is valid.
@@ -66,6 +68,13 @@ pub enum ResolveError {
 		/// Version of the other element.
 		other_version: Version,
 	},
+	/// Two packages to be installed conflict with each other.
+	Conflict {
+		/// The name of the package declaring the conflict.
+		name: String,
+		/// The name of the conflicting package.
+		other: String,
+	},
 }
 
 impl fmt::Display for ResolveError {
@@ -90,25 +99,41 @@ impl fmt::Display for ResolveError {
 					"Conflicting version `{other_version}` and `{required_version}` on dependency `{name}`!",
 				)?;
 			}
+			Self::Conflict {
+				name,
+				other,
+			} => {
+				write!(fmt, "Package `{name}` conflicts with package `{other}`!")?;
+			}
 		}
 		Ok(())
 	}
 }
 
 /// The type of dependency.
-#[derive(Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[derive(Clone, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub enum DependencyType {
 	#[serde(rename = "build")]
 	Build,
 	#[serde(rename = "run")]
+	#[default]
 	Run,
 	#[serde(rename = "build-and-run")]
 	BuildAndRun,
+	/// A dependency which is not required to run the package, but enables additional
+	/// functionalities. It is installed only on request.
+	#[serde(rename = "optional", alias = "recommended")]
+	Optional,
+	/// A dependency required only to run the package's test suite, when building it with checks
+	/// enabled.
+	#[serde(rename = "check")]
+	Check,
 }
 
-impl Default for DependencyType {
-	fn default() -> Self {
-		Self::Run
+impl fmt::Display for DependencyType {
+	/// Writes the name of the dependency type, as written in package metadata.
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		self.serialize(f)
 	}
 }
 
@@ -125,14 +150,125 @@ pub struct Dependency {
 	/// The version of the package must match the intersection of all the constraints.
 	#[serde(rename = "version")]
 	pub version_constraint: VersionConstraint,
+	/// The reason why the dependency is useful, for optional dependencies.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub reason: Option<String>,
 }
 
 impl fmt::Display for Dependency {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}: {}", self.name, self.version_constraint)?;
+		if let Some(reason) = &self.reason {
+			write!(f, " ({reason})")?;
+		}
+		Ok(())
+	}
+}
+
+/// A relation to other packages, matching the packages with the given name or providing it.
+#[derive(Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Relation {
+	/// The name of the package or capability.
+	pub name: String,
+	/// The constraint the version must match.
+	#[serde(default, rename = "version")]
+	pub version_constraint: VersionConstraint,
+}
+
+impl fmt::Display for Relation {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		write!(f, "{}: {}", self.name, self.version_constraint)
 	}
 }
 
+/// A virtual capability provided by a package.
+#[derive(Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Provided {
+	/// The name of the capability.
+	pub name: String,
+	/// The version of the capability, if any.
+	///
+	/// A capability without a version only satisfies relations without a version constraint.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub version: Option<Version>,
+}
+
+/// A patch applied to the sources of a package when it was built.
+#[derive(Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct AppliedPatch {
+	/// The name of the patch file.
+	pub name: String,
+	/// The SHA-256 checksum of the patch file, in hexadecimal.
+	pub sha256: String,
+}
+
+/// The compression format of a package's archive.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchiveFormat {
+	/// gzip
+	#[default]
+	Gzip,
+	/// xz
+	Xz,
+	/// Zstandard
+	Zstd,
+}
+
+impl ArchiveFormat {
+	/// All the supported formats.
+	pub const ALL: [Self; 3] = [Self::Gzip, Self::Xz, Self::Zstd];
+
+	/// Returns the extension of archives in this format, without the leading dot.
+	pub fn extension(self) -> &'static str {
+		match self {
+			Self::Gzip => "tar.gz",
+			Self::Xz => "tar.xz",
+			Self::Zstd => "tar.zst",
+		}
+	}
+
+	/// Tells whether the format is the default one.
+	pub fn is_default(&self) -> bool {
+		*self == Self::default()
+	}
+
+	/// If `file_name` has the extension of an archive, the function returns the file name without
+	/// it, along with the corresponding format.
+	pub fn strip_extension(file_name: &str) -> Option<(&str, Self)> {
+		Self::ALL.into_iter().find_map(|format| {
+			let stem = file_name.strip_suffix(format.extension())?;
+			Some((stem.strip_suffix('.')?, format))
+		})
+	}
+}
+
+impl fmt::Display for ArchiveFormat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let name = match self {
+			Self::Gzip => "gzip",
+			Self::Xz => "xz",
+			Self::Zstd => "zstd",
+		};
+		f.write_str(name)
+	}
+}
+
+impl FromStr for ArchiveFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"gzip" => Ok(Self::Gzip),
+			"xz" => Ok(Self::Xz),
+			"zstd" => Ok(Self::Zstd),
+			_ => Err(format!(
+				"invalid archive format `{s}`, expected `gzip`, `xz` or `zstd`"
+			)),
+		}
+	}
+}
+
 /// A package's description.
 #[derive(Clone, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct Package {
@@ -146,15 +282,58 @@ pub struct Package {
 	/// Dependencies required to build/run the package
 	#[serde(default, skip_serializing_if = "Vec::is_empty", rename = "dep")]
 	pub deps: Vec<Dependency>,
+	/// Packages which cannot be installed along with the package
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub conflicts: Vec<Relation>,
+	/// Packages superseded by the package, removed when it is installed
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub replaces: Vec<Relation>,
+	/// Virtual capabilities provided by the package
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub provides: Vec<Provided>,
+	/// Patches applied to the sources of the package when it was built
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub patches: Vec<AppliedPatch>,
+	/// The hash of the inputs the package was built from. Rebuilding the package from the same
+	/// inputs is not necessary
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub input_hash: Option<String>,
+	/// The compression format of the package's archive
+	#[serde(default, skip_serializing_if = "ArchiveFormat::is_default")]
+	pub archive_format: ArchiveFormat,
 }
 
 impl Package {
+	/// Parses a package from the TOML document `content`.
+	///
+	/// On failure, the error names the package, if possible, and the field that failed to parse.
+	pub fn parse(content: &str) -> Result<Self> {
+		let table: toml::Table = toml::from_str(content)?;
+		Self::from_table(table)
+	}
+
+	/// Deserializes a package from the given TOML table.
+	///
+	/// On failure, the error names the package, if possible, and the field that failed to parse.
+	pub fn from_table(table: toml::Table) -> Result<Self> {
+		let name = table
+			.get("name")
+			.and_then(|n| n.as_str())
+			.map(str::to_owned);
+		table.try_into().map_err(|e| match name {
+			Some(name) => anyhow!("invalid package `{name}`: {e}"),
+			None => anyhow!("invalid package: {e}"),
+		})
+	}
+
 	/// Loads a package from the metadata file.
 	///
 	/// If the package does not exist, the function returns `None`.
 	pub fn from_file(metadata_path: &Path) -> Result<Option<Package>> {
 		match fs::read_to_string(metadata_path) {
-			Ok(content) => Ok(Some(toml::from_str(&content)?)),
+			Ok(content) => Self::parse(&content)
+				.map(Some)
+				.map_err(|e| anyhow!("`{}`: {e}", metadata_path.display())),
 			Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
 			Err(e) => Err(e.into()),
 		}
@@ -170,15 +349,77 @@ impl Package {
 				bail!("invalid dependency name: {}", d.name);
 			}
 		}
+		let relations = self
+			.conflicts
+			.iter()
+			.map(|r| &r.name)
+			.chain(self.replaces.iter().map(|r| &r.name))
+			.chain(self.provides.iter().map(|p| &p.name));
+		for name in relations {
+			if !is_valid_name(name) {
+				bail!("invalid relation name: {name}");
+			}
+		}
 		Ok(())
 	}
 
+	/// Returns the optional dependencies of the package.
+	pub fn optional_deps(&self) -> impl Iterator<Item = &Dependency> {
+		self.deps
+			.iter()
+			.filter(|d| d.dep_type == DependencyType::Optional)
+	}
+
+	/// Tells whether the package satisfies a relation to `name` with the given
+	/// `version_constraint`, either by itself or through a capability it provides.
+	pub fn satisfies(&self, name: &str, version_constraint: &VersionConstraint) -> bool {
+		if self.name == name && version_constraint.is_valid(&self.version) {
+			return true;
+		}
+		self.provides
+			.iter()
+			.filter(|p| p.name == name)
+			.any(|p| match &p.version {
+				Some(v) => version_constraint.is_valid(v),
+				None => *version_constraint == VersionConstraint::Any,
+			})
+	}
+
+	/// Tells whether the package and `other` cannot be installed together.
+	///
+	/// Conflicts are symmetric: it is enough for one of the packages to declare it. A package
+	/// never conflicts with another version of itself.
+	pub fn conflicts_with(&self, other: &Self) -> bool {
+		if self.name == other.name {
+			return false;
+		}
+		let matches = |a: &Self, b: &Self| {
+			a.conflicts
+				.iter()
+				.any(|c| b.satisfies(&c.name, &c.version_constraint))
+		};
+		matches(self, other) || matches(other, self)
+	}
+
+	/// Tells whether the package supersedes `other`, which must then be removed when the
+	/// package is installed.
+	pub fn replaces(&self, other: &Self) -> bool {
+		self.name != other.name
+			&& self
+				.replaces
+				.iter()
+				.any(|r| r.name == other.name && r.version_constraint.is_valid(&other.version))
+	}
+
 	/// Resolves the dependencies of the package and inserts them into the given `HashMap`.
 	///
 	/// Arguments:
 	/// - `packages` is the `HashMap` which associates packages with their respective repository.
-	/// - `dep_type` is the type of dependencies to resolve. `BuildAndRun` resolves everything.
-	/// - `f` is a function used to get a package from its name and version.
+	/// - `dep_type` is the type of dependencies to resolve. `BuildAndRun` resolves everything,
+	///   except optional dependencies which are never resolved, and check dependencies which are
+	///   resolved only when requested explicitly.
+	/// - `f` is a function used to get a package from its name and version. It is also given the
+	///   packages selected so far, so that it can avoid selecting a package conflicting with them.
 	///
 	/// The function makes use of packages that are already in the `HashMap` and those which are
 	/// already installed to determine if there is a dependency error.
@@ -191,11 +432,22 @@ impl Package {
 		f: &mut F,
 	) -> io::Result<Result<(), Vec<ResolveError>>>
 	where
-		F: FnMut(&str, &VersionConstraint) -> Option<(Self, &'r Repository)>,
+		F: FnMut(
+			&str,
+			&VersionConstraint,
+			&HashMap<Self, &'r Repository>,
+		) -> Option<(Self, &'r Repository)>,
 	{
 		let mut errors = vec![];
 
 		for d in &self.deps {
+			// Optional dependencies are installed only on request
+			if d.dep_type == DependencyType::Optional {
+				continue;
+			}
+			if d.dep_type == DependencyType::Check && dep_type != DependencyType::Check {
+				continue;
+			}
 			// if filter by build & run, get all deps
 			if dep_type != DependencyType::BuildAndRun
 			    // if dep is build & run, we need it anyway
@@ -206,11 +458,15 @@ impl Package {
 				continue;
 			}
 			// TODO check already installed packages
-			// Get package in the installation list
-			let pkg = packages.keys().find(|p| p.name == d.name);
+			// Get package in the installation list, either by name or through what it provides
+			let pkg = packages.keys().find(|p| p.name == d.name).or_else(|| {
+				packages
+					.keys()
+					.find(|p| p.satisfies(&d.name, &d.version_constraint))
+			});
 			// Check for conflict
 			if let Some(pkg) = pkg {
-				if !d.version_constraint.is_valid(&pkg.version) {
+				if !pkg.satisfies(&d.name, &d.version_constraint) {
 					errors.push(ResolveError::VersionConflict {
 						name: d.name.clone(),
 						required_version: d.version_constraint.clone(),
@@ -222,7 +478,7 @@ impl Package {
 			}
 
 			// Resolve package, then resolve its dependencies
-			if let Some((p, repo)) = f(&d.name, &d.version_constraint) {
+			if let Some((p, repo)) = f(&d.name, &d.version_constraint, packages) {
 				// TODO Check for dependency cycles
 				// FIXME Possible stack overflow
 				// At this point, we should only need run dependencies, as we are past the build
@@ -257,25 +513,127 @@ pub struct InstalledPackage {
 	pub desc: Package,
 	/// The list of absolute paths to installed files.
 	pub files: Vec<PathBuf>,
+	/// The metadata of installed files, as listed in the package's file manifest.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub manifest: Vec<FileEntry>,
+	/// The name of the repository the package was installed from.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub repository: Option<String>,
+	/// The SHA-256 checksum of the package's archive, in hexadecimal.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub sha256: Option<String>,
+	/// Tells whether the package has been installed explicitly, as opposed to being installed as
+	/// a dependency of another package.
+	#[serde(default)]
+	pub explicit: bool,
+}
+
+/// Returns the conflicts between the given packages, as pairs of names.
+///
+/// Each pair is reported once.
+pub fn list_conflicts<'p>(
+	packages: impl IntoIterator<Item = &'p Package>,
+) -> Vec<(&'p str, &'p str)> {
+	let packages: Vec<_> = packages.into_iter().collect();
+	packages
+		.iter()
+		.enumerate()
+		.flat_map(|(i, p0)| {
+			packages[i + 1..]
+				.iter()
+				.filter(|p1| p0.conflicts_with(p1))
+				.map(|p1| (p0.name.as_str(), p1.name.as_str()))
+		})
+		.collect()
 }
 
 /// For the given list of packages, returns the list of dependencies that are not matched.
 pub fn list_unmatched_dependencies(
 	pkgs: &HashMap<String, InstalledPackage>,
 ) -> Vec<(&InstalledPackage, &Dependency)> {
-	pkgs.iter()
-		.flat_map(|(_, pkg)| {
+	pkgs.values()
+		.flat_map(|pkg| {
 			pkg.desc
 				.deps
 				.iter()
 				.filter(|dep| {
-					dep.dep_type != DependencyType::Build
-						&& pkgs
-							.get(&dep.name)
-							.map(|p| dep.version_constraint.is_valid(&p.desc.version))
-							.unwrap_or(false)
+					!matches!(
+						dep.dep_type,
+						DependencyType::Build | DependencyType::Optional | DependencyType::Check
+					) && pkgs
+						.get(&dep.name)
+						.map(|p| dep.version_constraint.is_valid(&p.desc.version))
+						.unwrap_or(false)
 				})
 				.map(move |dep| (pkg, dep))
 		})
 		.collect()
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn relations() {
+		let gcc = Package::parse(
+			r#"
+			name = "gcc"
+			version = "14.2"
+			description = ""
+			conflicts = [{ name = "clang" }]
+			replaces = [{ name = "gcc-old", version = "<14" }]
+			provides = [{ name = "cc", version = "14.2" }, { name = "c-compiler" }]
+			"#,
+		)
+		.unwrap();
+		let clang = Package::parse(
+			r#"
+			name = "clang"
+			version = "19.1"
+			description = ""
+			"#,
+		)
+		.unwrap();
+		let any = VersionConstraint::Any;
+		let ge14 = VersionConstraint::try_from(">=14").unwrap();
+		assert!(gcc.satisfies("gcc", &ge14));
+		assert!(gcc.satisfies("cc", &ge14));
+		assert!(!gcc.satisfies("cc", &VersionConstraint::try_from("<14").unwrap()));
+		assert!(gcc.satisfies("c-compiler", &any));
+		assert!(!gcc.satisfies("c-compiler", &ge14));
+		assert!(gcc.conflicts_with(&clang));
+		assert!(clang.conflicts_with(&gcc));
+		assert!(!gcc.conflicts_with(&gcc));
+		assert_eq!(list_conflicts([&gcc, &clang]), [("gcc", "clang")]);
+		let old = |version: &str| {
+			Package::parse(&format!(
+				"name = \"gcc-old\"\nversion = \"{version}\"\ndescription = \"\""
+			))
+			.unwrap()
+		};
+		assert!(gcc.replaces(&old("13.1")));
+		assert!(!gcc.replaces(&old("14.0")));
+	}
+
+	#[test]
+	fn archive_format() {
+		for format in ArchiveFormat::ALL {
+			let name = format!("foo_1.0.{}", format.extension());
+			assert_eq!(
+				ArchiveFormat::strip_extension(&name),
+				Some(("foo_1.0", format))
+			);
+			assert_eq!(format.to_string().parse(), Ok(format));
+		}
+		assert_eq!(ArchiveFormat::strip_extension("foo_1.0.meta"), None);
+		assert_eq!(ArchiveFormat::strip_extension("foo_1.0tar.gz"), None);
+		assert!(ArchiveFormat::from_str("bzip2").is_err());
+	}
+
+	#[test]
+	fn dependency_type_name() {
+		assert_eq!(DependencyType::BuildAndRun.to_string(), "build-and-run");
+		assert_eq!(DependencyType::Optional.to_string(), "optional");
+	}
+}
@@ -24,15 +24,16 @@
 pub mod remote;
 
 use crate::{
-	package::{self, DependencyType, Package},
+	package::{self, DependencyType, Package, ResolveError},
 	util::current_arch,
 	version::{Version, VersionConstraint},
 };
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 #[cfg(feature = "network")]
 use remote::Remote;
 use serde::{Deserialize, Serialize};
 use std::{
+	cmp::Ordering,
 	collections::HashMap,
 	fs,
 	path::{Path, PathBuf},
@@ -58,6 +59,59 @@ pub struct Index {
 	pub arch: HashMap<String, IndexArch>,
 }
 
+impl Index {
+	/// Parses an index from the TOML document `content`.
+	///
+	/// On failure, the error names the architecture and package that failed to parse, if
+	/// possible.
+	pub fn parse(content: &str) -> Result<Self> {
+		toml::from_str(content).map_err(|e| Self::locate_error(content).unwrap_or(e.into()))
+	}
+
+	/// Looks for the first invalid package in the index `content` and returns an error
+	/// describing it.
+	fn locate_error(content: &str) -> Option<anyhow::Error> {
+		let table: toml::Table = toml::from_str(content).ok()?;
+		let archs = table.get("arch")?.as_table()?;
+		for (arch, a) in archs {
+			let packages = a
+				.get("package")
+				.and_then(|p| p.as_array())
+				.into_iter()
+				.flatten()
+				.filter_map(|p| p.as_table());
+			for p in packages {
+				if let Err(e) = Package::from_table(p.clone()) {
+					return Some(anyhow!("architecture `{arch}`: {e}"));
+				}
+			}
+		}
+		None
+	}
+}
+
+/// Tells whether the given snapshot ID is valid.
+///
+/// IDs are used in the paths of snapshots on servers and in the list of remotes, so they may
+/// only contain ASCII alphanumeric characters, `-`, `_` and `.`, and cannot start with `.`.
+pub fn is_valid_snapshot_id(id: &str) -> bool {
+	!id.is_empty()
+		&& !id.starts_with('.')
+		&& id
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+/// Orders the candidates `p0` and `p1` for a relation to `name`.
+///
+/// A package with the given name is preferred over a package providing it. Then, the latest
+/// version is preferred.
+fn cmp_candidates(name: &str, p0: &Package, p1: &Package) -> Ordering {
+	(p0.name == name)
+		.cmp(&(p1.name == name))
+		.then_with(|| p0.version.cmp(&p1.version))
+}
+
 /// A local repository.
 pub struct Repository {
 	/// The path to the repository.
@@ -83,6 +137,15 @@ impl Repository {
 		&self.path
 	}
 
+	/// Returns the name identifying the repository: the host of its remote if any, else its path.
+	pub fn name(&self) -> String {
+		#[cfg(feature = "network")]
+		if let Some(remote) = &self.remote {
+			return remote.host.clone();
+		}
+		self.path.display().to_string()
+	}
+
 	/// Returns the remote associated with the repository.
 	#[cfg(feature = "network")]
 	pub fn get_remote(&self) -> Option<&Remote> {
@@ -96,8 +159,9 @@ impl Repository {
 
 	/// Reads the repository's index
 	pub fn read_index(&self) -> Result<Index> {
-		let content = fs::read_to_string(self.get_index_path())?;
-		Ok(toml::from_str(&content)?)
+		let path = self.get_index_path();
+		let content = fs::read_to_string(&path)?;
+		Index::parse(&content).map_err(|e| anyhow!("invalid index `{}`: {e}", path.display()))
 	}
 
 	/// Returns the path to a package's metadata
@@ -109,16 +173,18 @@ impl Repository {
 	}
 
 	/// Returns the path to a package's archive
-	pub fn get_archive_path(&self, arch: &str, name: &str, version: &Version) -> PathBuf {
-		self.path
-			.join("dist")
-			.join(arch)
-			.join(format!("{name}_{version}.tar.gz"))
+	pub fn get_archive_path(&self, arch: &str, package: &Package) -> PathBuf {
+		self.path.join("dist").join(arch).join(format!(
+			"{}_{}.{}",
+			package.name,
+			package.version,
+			package.archive_format.extension()
+		))
 	}
 
 	/// Tells whether the **archive** of a package is present in the repository.
-	pub fn is_in_cache(&self, arch: &str, name: &str, version: &Version) -> bool {
-		self.get_archive_path(arch, name, version).exists()
+	pub fn is_in_cache(&self, arch: &str, package: &Package) -> bool {
+		self.get_archive_path(arch, package).exists()
 	}
 
 	/// Returns a package in the repository
@@ -155,16 +221,30 @@ impl Repository {
 	///
 	/// Arguments:
 	/// - `arch` is the required architecture
-	/// - `name` is the name of the package
+	/// - `name` is the name of the package, or of a capability it provides
 	/// - `version_constraint` is the version constraint to match. If no constraint is specified,
 	///   the latest version is selected
 	///
+	/// A package with the given name is preferred over a package providing it.
+	///
 	/// If the package does not exist, the function returns `None`.
 	pub fn get_package_with_constraint(
 		&self,
 		arch: &str,
 		name: &str,
 		version_constraint: Option<&VersionConstraint>,
+	) -> Result<Option<Package>> {
+		self.get_package_matching(arch, name, version_constraint, |_| true)
+	}
+
+	/// Same as [`Self::get_package_with_constraint`], except only the candidates for which
+	/// `filter` returns `true` are considered.
+	pub fn get_package_matching<F: Fn(&Package) -> bool>(
+		&self,
+		arch: &str,
+		name: &str,
+		version_constraint: Option<&VersionConstraint>,
+		filter: F,
 	) -> Result<Option<Package>> {
 		let mut index = self.read_index()?;
 		// Remove to move the object out. We can do this since the index is dropped when the
@@ -172,20 +252,12 @@ impl Repository {
 		let Some(index_arch) = index.arch.remove(arch) else {
 			return Ok(None);
 		};
+		let version_constraint = version_constraint.unwrap_or(&VersionConstraint::Any);
 		let pkg = index_arch
 			.package
 			.into_iter()
-			.filter(|pkg| {
-				if pkg.name != name {
-					return false;
-				}
-				if let Some(c) = version_constraint {
-					c.is_valid(&pkg.version)
-				} else {
-					true
-				}
-			})
-			.max_by(|p0, p1| p0.version.cmp(&p1.version));
+			.filter(|pkg| pkg.satisfies(name, version_constraint) && filter(pkg))
+			.max_by(|p0, p1| cmp_candidates(name, p0, p1));
 		Ok(pkg)
 	}
 }
@@ -219,16 +291,30 @@ pub fn get_package<'a>(
 ///
 /// Arguments:
 /// - `arch` is the required architecture
-/// - `name` is the name of the package
+/// - `name` is the name of the package, or of a capability it provides
 /// - `version_constraint` is the version constraint to match. If no constraint is specified, the
 ///   latest version is selected
 ///
+/// A package with the given name is preferred over a package providing it.
+///
 /// If the package does not exist, the function returns `None`.
 pub fn get_package_with_constraint<'a>(
 	repos: &'a [Repository],
 	arch: &str,
 	name: &str,
 	version_constraint: Option<&VersionConstraint>,
+) -> Result<Option<(&'a Repository, Package)>> {
+	get_package_matching(repos, arch, name, version_constraint, |_| true)
+}
+
+/// Same as [`get_package_with_constraint`], except only the candidates for which `filter`
+/// returns `true` are considered.
+pub fn get_package_matching<'a, F: Fn(&Package) -> bool>(
+	repos: &'a [Repository],
+	arch: &str,
+	name: &str,
+	version_constraint: Option<&VersionConstraint>,
+	filter: F,
 ) -> Result<Option<(&'a Repository, Package)>> {
 	if !package::is_valid_name(name) {
 		bail!("invalid package name: {name}");
@@ -236,39 +322,57 @@ pub fn get_package_with_constraint<'a>(
 	Ok(repos
 		.iter()
 		.filter_map(|repo| {
-			match repo.get_package_with_constraint(arch, name, version_constraint) {
+			match repo.get_package_matching(arch, name, version_constraint, &filter) {
 				Ok(Some(pack)) => Some((repo, pack)),
 				_ => None,
 			}
 		})
-		.max_by(|(_, p0), (_, p1)| p0.version.cmp(&p1.version)))
+		.max_by(|(_, p0), (_, p1)| cmp_candidates(name, p0, p1)))
 }
 
-/// Appends recursive dependencies to given packages.
+/// Resolves the recursive dependencies of the given packages once.
 ///
-/// Arguments:
-/// - `packages` is top level packages to resolve dependencies for.
-/// - `repos` is repositories to search packages into.
-/// - `dep_type` is the type of dependencies to resolve. `BuildAndRun` resolves everything.
-/// - `arch` is the architecture to use.
-pub fn get_recursive_dependencies<'r>(
+/// `combined` maps dependency names to a constraint which the selected version must match in
+/// addition to the constraint of the dependent.
+///
+/// The function returns the list of all packages, dependencies included, the constraints each
+/// dependency has been selected with and the resolution errors.
+fn resolve_once<'r>(
 	packages: &PackagesWithRepositoryMap<'r>,
 	repos: &'r [Repository],
-	dep_type: DependencyType,
+	dep_type: &DependencyType,
 	arch: &str,
-) -> Result<PackagesWithRepositoryMap<'r>> {
-	let mut failed = false;
+	combined: &HashMap<String, VersionConstraint>,
+) -> Result<(
+	PackagesWithRepositoryMap<'r>,
+	HashMap<String, VersionConstraint>,
+	Vec<ResolveError>,
+)> {
 	// The list of all packages, dependencies included
 	let mut total_packages = packages.clone();
+	let mut selected = HashMap::new();
+	let mut errors = vec![];
 	// TODO check dependencies for all packages at once to avoid duplicate errors
-	// Resolving dependencies
 	for package in packages.keys() {
 		let res = package.resolve_dependencies(
 			&mut total_packages,
 			dep_type.clone(),
-			&mut |name, version_constraint| {
+			&mut |name, version_constraint, selected_pkgs| {
+				let version_constraint = match combined.get(name) {
+					Some(c) => c.intersect(version_constraint)?,
+					None => version_constraint.clone(),
+				};
+				// Skip candidates conflicting with a package that has already been selected
+				let no_conflict =
+					|pkg: &Package| !selected_pkgs.keys().any(|p| p.conflicts_with(pkg));
 				// TODO yet another call for reading whole repo index
-				let res = get_package_with_constraint(repos, arch, name, Some(version_constraint));
+				let res = get_package_matching(
+					repos,
+					arch,
+					name,
+					Some(&version_constraint),
+					no_conflict,
+				);
 				let pkg = match res {
 					Ok(p) => p,
 					Err(e) => {
@@ -276,19 +380,92 @@ pub fn get_recursive_dependencies<'r>(
 						return None;
 					}
 				};
-				match pkg {
-					Some((repo, pkg)) => Some((pkg, repo)),
-					// If not present, check on remote
-					None => todo!(),
-				}
+				// If no version matches, `resolve_dependencies` reports the dependency as not
+				// found
+				let (repo, pkg) = pkg?;
+				selected.insert(name.to_owned(), version_constraint);
+				Some((pkg, repo))
 			},
 		)?;
 		if let Err(errs) = res {
-			for e in errs {
-				eprintln!("{e}");
+			errors.extend(errs);
+		}
+	}
+	Ok((total_packages, selected, errors))
+}
+
+/// Appends recursive dependencies to given packages.
+///
+/// When dependents require different versions of the same dependency, the dependency is
+/// resolved again with the intersection of their constraints.
+///
+/// Arguments:
+/// - `packages` is top level packages to resolve dependencies for.
+/// - `repos` is repositories to search packages into.
+/// - `dep_type` is the type of dependencies to resolve. `BuildAndRun` resolves everything.
+/// - `arch` is the architecture to use.
+pub fn get_recursive_dependencies<'r>(
+	packages: &PackagesWithRepositoryMap<'r>,
+	repos: &'r [Repository],
+	dep_type: DependencyType,
+	arch: &str,
+) -> Result<PackagesWithRepositoryMap<'r>> {
+	let mut failed = false;
+	let mut combined = HashMap::new();
+	let (total_packages, errors) = loop {
+		let (total_packages, selected, errors) =
+			resolve_once(packages, repos, &dep_type, arch, &combined)?;
+		// Narrow the constraints of conflicting dependencies selected by a dependent. Since each
+		// constraint can only get narrower, this terminates
+		let mut retry = false;
+		for e in &errors {
+			let ResolveError::VersionConflict {
+				name,
+				required_version,
+				..
+			} = e
+			else {
+				continue;
+			};
+			let Some(c) = selected
+				.get(name)
+				.and_then(|c| c.intersect(required_version))
+			else {
+				continue;
+			};
+			if combined.get(name) != Some(&c) {
+				combined.insert(name.clone(), c);
+				retry = true;
+			}
+		}
+		if !retry {
+			break (total_packages, errors);
+		}
+	};
+	for mut e in errors {
+		// Report the constraints dependencies have been narrowed to
+		if let ResolveError::NotFound {
+			name,
+			version_constraint,
+		} = &mut e
+		{
+			if let Some(c) = combined
+				.get(name)
+				.and_then(|c| c.intersect(version_constraint))
+			{
+				*version_constraint = c;
 			}
-			failed = true;
 		}
+		eprintln!("{e}");
+		failed = true;
+	}
+	for (name, other) in package::list_conflicts(total_packages.keys()) {
+		let e = package::ResolveError::Conflict {
+			name: name.to_owned(),
+			other: other.to_owned(),
+		};
+		eprintln!("{e}");
+		failed = true;
 	}
 	if failed {
 		// TODO better exception handling
@@ -296,3 +473,117 @@ pub fn get_recursive_dependencies<'r>(
 	}
 	Ok(total_packages)
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::util;
+	use std::env;
+
+	/// Creates a repository with the given packages, each described by its name, its version and
+	/// the constraint of its dependency on `libc`, if any.
+	fn create_repo(packages: &[(&str, &str, Option<&str>)]) -> Repository {
+		let mut index = String::new();
+		for (name, version, dep) in packages {
+			index.push_str(&format!(
+				"[[arch.test.package]]\nname = \"{name}\"\nversion = \"{version}\"\ndescription = \"\"\n"
+			));
+			if let Some(dep) = dep {
+				index.push_str(&format!(
+					"dep = [{{ type = \"run\", name = \"libc\", version = \"{dep}\" }}]\n"
+				));
+			}
+		}
+		create_repo_from_index(&index)
+	}
+
+	/// Creates a repository with the given `index`.
+	fn create_repo_from_index(index: &str) -> Repository {
+		let path = util::create_tmp_dir(env::temp_dir()).unwrap();
+		fs::write(path.join("index"), index).unwrap();
+		Repository::local(path)
+	}
+
+	/// Resolves the dependencies of the packages `names` from `repo`, returning the version of
+	/// `libc` that is selected.
+	fn resolve_libc(repo: Repository, names: &[&str]) -> Option<String> {
+		let repos = [repo];
+		let packages = names
+			.iter()
+			.map(|name| {
+				let (repo, pkg) = get_package_with_constraint(&repos, "test", name, None)
+					.unwrap()
+					.unwrap();
+				(pkg, repo)
+			})
+			.collect();
+		let res = get_recursive_dependencies(&packages, &repos, DependencyType::Run, "test");
+		fs::remove_dir_all(repos[0].get_path()).unwrap();
+		let total = res.ok()?;
+		let libc = total.keys().find(|p| p.name == "libc").unwrap();
+		Some(libc.version.to_string())
+	}
+
+	#[test]
+	fn intersect_constraints() {
+		let repo = create_repo(&[
+			("app", "1.0", Some(">=1.0")),
+			("tool", "1.0", Some("<2.0")),
+			("libc", "1.0", None),
+			("libc", "1.5", None),
+			("libc", "2.0-rc1", None),
+			("libc", "2.0", None),
+		]);
+		assert_eq!(resolve_libc(repo, &["app", "tool"]).as_deref(), Some("1.5"));
+	}
+
+	#[test]
+	fn unsatisfiable_constraints() {
+		// Disjoint ranges
+		let repo = create_repo(&[
+			("app", "1.0", Some(">=2.0")),
+			("tool", "1.0", Some("<1.0")),
+			("libc", "0.5", None),
+			("libc", "2.0", None),
+		]);
+		assert_eq!(resolve_libc(repo, &["app", "tool"]), None);
+		// No version in the intersection
+		let repo = create_repo(&[
+			("app", "1.0", Some(">=1.0")),
+			("tool", "1.0", Some("<2.0")),
+			("libc", "0.5", None),
+			("libc", "2.0", None),
+		]);
+		assert_eq!(resolve_libc(repo, &["app", "tool"]), None);
+	}
+
+	#[test]
+	fn skip_conflicting_candidates() {
+		let repo = create_repo_from_index(
+			r#"
+			[[arch.test.package]]
+			name = "app"
+			version = "1.0"
+			description = ""
+			dep = [{ type = "run", name = "libc", version = "*" }]
+
+			[[arch.test.package]]
+			name = "tool"
+			version = "1.0"
+			description = ""
+			conflicts = [{ name = "libc", version = ">=2.0" }]
+
+			[[arch.test.package]]
+			name = "libc"
+			version = "1.0"
+			description = ""
+
+			[[arch.test.package]]
+			name = "libc"
+			version = "2.0"
+			description = ""
+			"#,
+		);
+		assert_eq!(resolve_libc(repo, &["app", "tool"]).as_deref(), Some("1.0"));
+	}
+}
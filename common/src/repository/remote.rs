@@ -27,18 +27,24 @@ use anyhow::{anyhow, bail, Result};
 use reqwest::StatusCode;
 use std::{
 	borrow::Borrow,
+	cmp::Ordering,
 	collections::HashSet,
 	fs,
 	fs::{File, OpenOptions},
+	hash::{Hash, Hasher},
 	io,
 	io::{BufRead, BufReader, BufWriter, Write},
 };
 
 /// A remote host.
-#[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+///
+/// Remotes are identified by their host only.
+#[derive(Clone)]
 pub struct Remote {
 	/// The host's address and port (optional).
 	pub host: String,
+	/// The ID of the snapshot to use. If `None`, the latest index of the remote is used.
+	pub snapshot: Option<String>,
 }
 
 impl Borrow<str> for Remote {
@@ -47,8 +53,37 @@ impl Borrow<str> for Remote {
 	}
 }
 
+impl Eq for Remote {}
+
+impl PartialEq for Remote {
+	fn eq(&self, other: &Self) -> bool {
+		self.host == other.host
+	}
+}
+
+impl Hash for Remote {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.host.hash(state);
+	}
+}
+
+impl Ord for Remote {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.host.cmp(&other.host)
+	}
+}
+
+impl PartialOrd for Remote {
+	fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
 impl Remote {
 	/// Loads and returns the list of remote hosts.
+	///
+	/// Each line of the list is a host, optionally followed by the ID of the snapshot to use,
+	/// separated by a space.
 	pub fn load_list(env: &Environment) -> io::Result<HashSet<Self>> {
 		let path = env.sysroot().join(REMOTES_LIST);
 		let file = match File::open(path) {
@@ -59,9 +94,14 @@ impl Remote {
 		let reader = BufReader::new(file);
 		reader
 			.lines()
-			.map(|host| {
-				host.map(|host| Self {
-					host,
+			.filter(|line| !matches!(line, Ok(l) if l.trim().is_empty()))
+			.map(|line| {
+				line.map(|line| {
+					let mut fields = line.split_whitespace();
+					Self {
+						host: fields.next().unwrap_or_default().to_owned(),
+						snapshot: fields.next().map(str::to_owned),
+					}
 				})
 			})
 			.collect()
@@ -79,6 +119,10 @@ impl Remote {
 		let mut writer = BufWriter::new(file);
 		for r in remotes {
 			writer.write_all(r.host.as_bytes())?;
+			if let Some(snapshot) = &r.snapshot {
+				writer.write_all(b" ")?;
+				writer.write_all(snapshot.as_bytes())?;
+			}
 			writer.write_all(b"\n")?;
 		}
 		Ok(())
@@ -111,12 +155,20 @@ impl Remote {
 		}
 	}
 
+	/// Returns the URL of the remote's index, or the index of the selected snapshot if any.
+	pub fn index_url(&self) -> String {
+		match &self.snapshot {
+			Some(id) => format!("https://{}/snapshots/{id}/index", self.host),
+			None => format!("https://{}/index", self.host),
+		}
+	}
+
 	/// Fetches the remote's index
 	///
 	/// The function returns the number of packages found
 	pub async fn fetch_index(&self, env: &Environment) -> Result<usize> {
 		let client = reqwest::Client::new();
-		let url = format!("https://{}/index", self.host);
+		let url = self.index_url();
 		let response = client
 			.get(url)
 			.header("User-Agent", USER_AGENT)
@@ -128,7 +180,7 @@ impl Remote {
 		}
 		// Check the index is valid and get package count
 		let index = response.text().await?;
-		let parsed_index: Index = toml::from_str(&index)?;
+		let parsed_index = Index::parse(&index)?;
 		let cnt = parsed_index
 			.arch
 			.get(env.arch())
@@ -143,8 +195,12 @@ impl Remote {
 	/// Returns the download URL for the given `package`.
 	pub fn download_url(&self, arch: &str, package: &Package) -> String {
 		format!(
-			"https://{}/dist/{}/{}_{}.tar.gz",
-			self.host, arch, package.name, package.version
+			"https://{}/dist/{}/{}_{}.{}",
+			self.host,
+			arch,
+			package.name,
+			package.version,
+			package.archive_format.extension()
 		)
 	}
 
@@ -174,7 +230,7 @@ pub async fn download_packages<'r>(
 	let mut futures = Vec::new();
 	// TODO download biggest packages first (sort_unstable by decreasing size)
 	for (pkg, repo) in total_packages {
-		if repo.is_in_cache(arch, &pkg.name, &pkg.version) {
+		if repo.is_in_cache(arch, pkg) {
 			println!("`{}` is in cache.", &pkg.name);
 			continue;
 		}
@@ -188,7 +244,7 @@ pub async fn download_packages<'r>(
 					use crate::download::DownloadTask;
 					use std::fs::File;
 
-					let path = repo.get_archive_path(arch, &pkg.name, &pkg.version);
+					let path = repo.get_archive_path(arch, pkg);
 					// Ensure the parent directory exists
 					if let Some(parent) = path.parent() {
 						fs::create_dir_all(parent)?;
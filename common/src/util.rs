@@ -21,7 +21,9 @@
 use bzip2::read::BzDecoder;
 use flate2::read::GzDecoder;
 use rand::{distr::Alphanumeric, rng, RngExt};
+use sha2::{Digest, Sha256};
 use std::{
+	fmt::Write,
 	fs,
 	fs::{File, OpenOptions},
 	io,
@@ -31,6 +33,7 @@ use std::{
 };
 use tar::Archive;
 use xz2::read::XzDecoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 fn create_tmp<T, F: Fn(&Path) -> io::Result<T>>(parent: &Path, f: F) -> io::Result<(PathBuf, T)> {
 	fs::create_dir_all(parent)?;
@@ -98,19 +101,57 @@ pub fn decompress(src: &mut File, dest: &Path) -> io::Result<()> {
 }
 
 /// Reads the package archive at the given path and returns an instance for it.
-pub fn read_package_archive(path: &Path) -> io::Result<Archive<GzDecoder<File>>> {
-	let mut archive = Archive::new(GzDecoder::new(File::open(path)?));
+///
+/// The compression format of the archive is detected from its content.
+pub fn read_package_archive(path: &Path) -> io::Result<Archive<Box<dyn Read>>> {
+	let file = File::open(path)?;
+	let mut buf = [0; 6];
+	file.read_exact_at(&mut buf, 0)?;
+	let file_type = infer::get(&buf).map(|t| t.mime_type());
+	let stream: Box<dyn Read> = match file_type {
+		Some("application/gzip") => Box::new(GzDecoder::new(file)),
+		Some("application/x-xz") => Box::new(XzDecoder::new(file)),
+		Some("application/zstd") => Box::new(ZstdDecoder::new(file)?),
+		Some(file_type) => {
+			return Err(io::Error::other(format!(
+				"Invalid or unsupported package archive format: {file_type}"
+			)))
+		}
+		None => return Err(io::Error::other("Could not determine archive format")),
+	};
+	let mut archive = Archive::new(stream);
 	archive.set_overwrite(true);
 	archive.set_preserve_permissions(true);
 	Ok(archive)
 }
 
+/// Encodes the given bytes in lowercase hexadecimal.
+pub fn to_hex(bytes: &[u8]) -> String {
+	bytes.iter().fold(String::new(), |mut s, b| {
+		let _ = write!(s, "{b:02x}");
+		s
+	})
+}
+
+/// Computes the SHA-256 checksum of the file at `path`, returned in hexadecimal.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+	let mut file = File::open(path)?;
+	let mut hasher = Sha256::new();
+	io::copy(&mut file, &mut hasher)?;
+	Ok(to_hex(&hasher.finalize()))
+}
+
 /// Copies the content of the directory `src` to the directory `dst` recursively.
 ///
+/// If `dst` already exists, the content of `src` is merged into it.
+///
 /// **Note**: the parent directory of `dst` must exist.
 pub fn recursive_copy(src: &Path, dst: &Path) -> io::Result<()> {
 	let src_metadata = fs::metadata(src)?;
-	fs::create_dir(dst)?;
+	match fs::create_dir(dst) {
+		Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+		_ => {}
+	}
 	for entry in fs::read_dir(src)? {
 		let from = entry?;
 		let to = dst.join(from.file_name());
@@ -17,18 +17,248 @@
  */
 
 //! The version structure represents the version of a package.
+//!
+//! A version has the form `[epoch:]upstream[-pre-release][-revision]`, where:
+//! - `epoch` is a number allowing to reset the ordering when the versioning scheme of a package
+//!   changes. Defaults to `0`
+//! - `upstream` is the version of the software, made of alphanumeric components separated by dots
+//!   (for example `1.2.3`, `2024a` or `1.1.1w`)
+//! - `pre-release` is an alphanumeric identifier (for example `rc1` or `beta.2`). A pre-release is
+//!   ordered before the corresponding release
+//! - `revision` is the number of the packaging revision, for packages which have been rebuilt
+//!   without changing the upstream version
+//!
+//! A suffix made only of digits is always interpreted as a revision.
+//!
+//! Versions are compared by epoch, then upstream version, then pre-release, then revision.
+//! Components are compared naturally: sequences of digits are compared numerically, sequences of
+//! letters are compared lexicographically, and digits are ordered after letters.
 
 use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
 use std::{
 	cmp::{max, Ordering},
 	fmt,
-	num::ParseIntError,
+	hash::{Hash, Hasher},
 };
 
+/// Error returned when a version or version constraint cannot be parsed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+	/// The invalid input.
+	pub input: String,
+	/// The offset in bytes in `input` at which the error occurred.
+	pub position: usize,
+	/// Description of the token that was expected at `position`.
+	pub expected: &'static str,
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"cannot parse `{}`: expected {} at position {}, ",
+			self.input, self.expected, self.position
+		)?;
+		match self.input[self.position..].chars().next() {
+			Some(c) => write!(f, "found `{c}`"),
+			None => write!(f, "found end of input"),
+		}
+	}
+}
+
+/// Cursor over a slice of the input of the parser.
+struct Cursor<'s> {
+	/// The whole input.
+	input: &'s str,
+	/// The current position in `input`.
+	pos: usize,
+	/// The end of the slice to parse in `input`.
+	end: usize,
+}
+
+impl<'s> Cursor<'s> {
+	/// Creates a cursor over `input[start..end]`, ignoring surrounding whitespaces.
+	fn new(input: &'s str, start: usize, end: usize) -> Self {
+		let slice = &input[start..end];
+		Self {
+			input,
+			pos: start + (slice.len() - slice.trim_start().len()),
+			end: start + slice.trim_end().len(),
+		}
+	}
+
+	/// Returns the remaining input.
+	fn rest(&self) -> &'s str {
+		&self.input[self.pos..self.end]
+	}
+
+	/// Returns the next character without consuming it.
+	fn peek(&self) -> Option<char> {
+		self.rest().chars().next()
+	}
+
+	/// If the next character is `c`, consumes it and returns `true`.
+	fn eat(&mut self, c: char) -> bool {
+		let matches = self.peek() == Some(c);
+		if matches {
+			self.pos += c.len_utf8();
+		}
+		matches
+	}
+
+	/// Returns an error at the current position.
+	fn err(&self, expected: &'static str) -> ParseError {
+		self.err_at(self.pos, expected)
+	}
+
+	/// Returns an error at the given position.
+	fn err_at(&self, position: usize, expected: &'static str) -> ParseError {
+		ParseError {
+			input: self.input.to_owned(),
+			position,
+			expected,
+		}
+	}
+
+	/// Parses a number that fits in 32 bits.
+	fn number(&mut self, expected: &'static str) -> Result<u32, ParseError> {
+		let start = self.pos;
+		let rest = self.rest();
+		let len = rest
+			.find(|c: char| !c.is_ascii_digit())
+			.unwrap_or(rest.len());
+		if len == 0 {
+			return Err(self.err(expected));
+		}
+		self.pos += len;
+		rest[..len]
+			.parse()
+			.map_err(|_| self.err_at(start, "a number fitting in 32 bits"))
+	}
+
+	/// Parses a list of alphanumeric components separated by dots.
+	fn components(&mut self) -> Result<Vec<String>, ParseError> {
+		let mut components = vec![];
+		loop {
+			let rest = self.rest();
+			let len = rest
+				.find(|c: char| !c.is_ascii_alphanumeric())
+				.unwrap_or(rest.len());
+			if len == 0 {
+				return Err(self.err("a digit or a letter"));
+			}
+			components.push(rest[..len].to_owned());
+			self.pos += len;
+			if !self.eat('.') {
+				break;
+			}
+		}
+		Ok(components)
+	}
+}
+
+/// Splits the component `s` into sequences of either digits or letters.
+fn runs(s: &str) -> impl Iterator<Item = &str> {
+	let mut rest = s;
+	std::iter::from_fn(move || {
+		let first = rest.chars().next()?;
+		let digit = first.is_ascii_digit();
+		let end = rest
+			.find(|c: char| c.is_ascii_digit() != digit)
+			.unwrap_or(rest.len());
+		let (run, r) = rest.split_at(end);
+		rest = r;
+		Some(run)
+	})
+}
+
+/// Compares two components naturally.
+fn cmp_component(a: &str, b: &str) -> Ordering {
+	let mut a = runs(a);
+	let mut b = runs(b);
+	loop {
+		let (a, b) = match (a.next(), b.next()) {
+			(None, None) => return Ordering::Equal,
+			(None, Some(_)) => return Ordering::Less,
+			(Some(_), None) => return Ordering::Greater,
+			(Some(a), Some(b)) => (a, b),
+		};
+		let a_digit = a.starts_with(|c: char| c.is_ascii_digit());
+		let b_digit = b.starts_with(|c: char| c.is_ascii_digit());
+		let cmp = match (a_digit, b_digit) {
+			(true, true) => {
+				// Compare numerically without overflowing
+				let a = a.trim_start_matches('0');
+				let b = b.trim_start_matches('0');
+				a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+			}
+			(true, false) => Ordering::Greater,
+			(false, true) => Ordering::Less,
+			(false, false) => a.cmp(b),
+		};
+		if cmp != Ordering::Equal {
+			return cmp;
+		}
+	}
+}
+
+/// Compares two lists of components. Missing components are considered to be `0`.
+fn cmp_components(a: &[String], b: &[String]) -> Ordering {
+	let len = max(a.len(), b.len());
+	for i in 0..len {
+		let left = a.get(i).map(String::as_str).unwrap_or("0");
+		let right = b.get(i).map(String::as_str).unwrap_or("0");
+		let cmp = cmp_component(left, right);
+		if cmp != Ordering::Equal {
+			return cmp;
+		}
+	}
+	Ordering::Equal
+}
+
+/// Returns the normalized form of the list of components `c`.
+///
+/// Two lists of components compare equal if and only if their normalized forms are equal:
+/// leading zeros of numbers and trailing zero components are removed.
+fn normalize_components(c: &[String]) -> Vec<Vec<&str>> {
+	let mut c: Vec<Vec<&str>> = c
+		.iter()
+		.map(|c| {
+			runs(c)
+				.map(|run| match run.starts_with(|c: char| c.is_ascii_digit()) {
+					true => run.trim_start_matches('0'),
+					false => run,
+				})
+				.collect()
+		})
+		.collect();
+	while c.last().is_some_and(|c| *c == [""]) {
+		c.pop();
+	}
+	c
+}
+
+/// Returns the numeric value of the leading digits of the component `c`, or `0` if there is none.
+fn component_value(c: &str) -> u64 {
+	let end = c.find(|c: char| !c.is_ascii_digit()).unwrap_or(c.len());
+	c[..end].parse().unwrap_or(0)
+}
+
 /// A package version.
-#[derive(Clone, Eq, Hash, PartialEq)]
+///
+/// Versions are compared by value: for example, `1.0`, `1.0.0` and `1.00` are equal.
+#[derive(Clone)]
 pub struct Version {
-	components: Vec<u32>,
+	/// The epoch.
+	epoch: u32,
+	/// The components of the upstream version.
+	components: Vec<String>,
+	/// The components of the pre-release identifier, if any.
+	pre_release: Option<Vec<String>>,
+	/// The packaging revision, if any.
+	revision: Option<u32>,
 }
 
 impl Serialize for Version {
@@ -52,32 +282,102 @@ impl<'de> Deserialize<'de> for Version {
 }
 
 impl TryFrom<&str> for Version {
-	type Error = ParseIntError;
+	type Error = ParseError;
 
 	fn try_from(value: &str) -> Result<Self, Self::Error> {
-		value
-			.trim()
-			.split('.')
-			.map(|n| n.parse::<u32>())
-			.collect::<Result<Vec<_>, _>>()
-			.map(|numbers| Self {
-				components: numbers,
-			})
+		Self::parse(value, 0, value.len())
 	}
 }
 
-impl Ord for Version {
-	fn cmp(&self, other: &Self) -> Ordering {
-		let len = max(self.components.len(), other.components.len());
-		for i in 0..len {
-			let left = self.components.get(i).unwrap_or(&0);
-			let right = self.components.get(i).unwrap_or(&0);
-			let cmp = left.cmp(right);
-			if cmp != Ordering::Equal {
-				return cmp;
+impl Version {
+	/// Parses the version in `input[start..end]`.
+	///
+	/// Positions in errors are relative to the whole `input`.
+	fn parse(input: &str, start: usize, end: usize) -> Result<Self, ParseError> {
+		let mut cursor = Cursor::new(input, start, end);
+		let epoch = if cursor.rest().contains(':') {
+			let epoch = cursor.number("an epoch")?;
+			if !cursor.eat(':') {
+				return Err(cursor.err("`:`"));
 			}
+			epoch
+		} else {
+			0
+		};
+		let components = cursor.components()?;
+		// Groups of components following a `-`, along with their position
+		let mut groups = vec![];
+		while cursor.peek().is_some() {
+			if !cursor.eat('-') {
+				return Err(cursor.err("`.`, `-` or end of input"));
+			}
+			groups.push((cursor.pos, cursor.components()?));
+		}
+		// A trailing group made only of digits is the revision
+		let revision = match groups.last() {
+			Some((pos, group))
+				if group.len() == 1 && group[0].chars().all(|c| c.is_ascii_digit()) =>
+			{
+				let pos = *pos;
+				groups.pop();
+				Some(Cursor::new(input, pos, cursor.end).number("a revision number")?)
+			}
+			_ => None,
+		};
+		if let Some((pos, _)) = groups.get(1) {
+			return Err(cursor.err_at(*pos, "a revision number"));
 		}
-		Ordering::Equal
+		let pre_release = groups.pop().map(|(_, group)| group);
+		Ok(Self {
+			epoch,
+			components,
+			pre_release,
+			revision,
+		})
+	}
+
+	/// Returns the version made of the components of `self` before `idx`, followed by the
+	/// component at `idx` incremented by one.
+	///
+	/// The returned version has the same epoch, without pre-release nor revision.
+	fn bump(&self, idx: usize) -> Self {
+		let mut components = self.components[..idx].to_vec();
+		let value = self
+			.components
+			.get(idx)
+			.map(|c| component_value(c))
+			.unwrap_or(0);
+		components.push((value + 1).to_string());
+		Self {
+			epoch: self.epoch,
+			components,
+			pre_release: None,
+			revision: None,
+		}
+	}
+
+	/// Tells whether the version is a pre-release of `other`, such as `2.0-rc1` for `2.0`.
+	fn is_pre_release_of(&self, other: &Self) -> bool {
+		self.pre_release.is_some()
+			&& other.pre_release.is_none()
+			&& self.epoch == other.epoch
+			&& normalize_components(&self.components) == normalize_components(&other.components)
+	}
+}
+
+impl Ord for Version {
+	fn cmp(&self, other: &Self) -> Ordering {
+		self.epoch
+			.cmp(&other.epoch)
+			.then_with(|| cmp_components(&self.components, &other.components))
+			.then_with(|| match (&self.pre_release, &other.pre_release) {
+				(None, None) => Ordering::Equal,
+				// A pre-release comes before the release
+				(None, Some(_)) => Ordering::Greater,
+				(Some(_), None) => Ordering::Less,
+				(Some(a), Some(b)) => cmp_components(a, b),
+			})
+			.then_with(|| self.revision.unwrap_or(0).cmp(&other.revision.unwrap_or(0)))
 	}
 }
 
@@ -87,22 +387,53 @@ impl PartialOrd for Version {
 	}
 }
 
+impl PartialEq for Version {
+	fn eq(&self, other: &Self) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for Version {}
+
+impl Hash for Version {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		// Consistent with `Ord`
+		self.epoch.hash(state);
+		normalize_components(&self.components).hash(state);
+		self.pre_release
+			.as_deref()
+			.map(normalize_components)
+			.hash(state);
+		self.revision.unwrap_or(0).hash(state);
+	}
+}
+
 impl fmt::Display for Version {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-		for i in 0..self.components.len() {
-			write!(f, "{}", self.components[i])?;
-			if i + 1 < self.components.len() {
-				write!(f, ".")?;
-			}
+		if self.epoch != 0 {
+			write!(f, "{}:", self.epoch)?;
+		}
+		write!(f, "{}", self.components.join("."))?;
+		if let Some(pre_release) = &self.pre_release {
+			write!(f, "-{}", pre_release.join("."))?;
+		}
+		if let Some(revision) = self.revision {
+			write!(f, "-{revision}")?;
 		}
 		Ok(())
 	}
 }
 
 /// Constraints on a package's dependencies.
-#[derive(Clone, Eq, Hash, PartialEq)]
+///
+/// A constraint is written as a list of alternatives separated by `||`, each alternative being a
+/// list of constraints separated by `,`, all of which must match. For example,
+/// `>=1.2, <2.0 || >=3.0` matches versions from `1.2` (included) to `2.0` (excluded), and
+/// versions from `3.0`.
+#[derive(Clone, Default, Eq, Hash, PartialEq)]
 pub enum VersionConstraint {
 	/// Any version match.
+	#[default]
 	Any,
 	/// The version must be equal to the given version.
 	Equal(Version),
@@ -114,6 +445,20 @@ pub enum VersionConstraint {
 	GreaterOrEqual(Version),
 	/// The version must be greater than the given version.
 	Greater(Version),
+	/// `~` shorthand: the version must be greater than or equal to the given version, without
+	/// changing the minor version if specified, or the major version otherwise. For example,
+	/// `~1.2.3` is equivalent to `>=1.2.3, <1.3`.
+	Tilde(Version),
+	/// `^` shorthand: the version must be greater than or equal to the given version, without
+	/// changing its first non-zero component. For example, `^1.2.3` is equivalent to
+	/// `>=1.2.3, <2` and `^0.2.3` to `>=0.2.3, <0.3`.
+	Caret(Version),
+	/// All the constraints must match.
+	///
+	/// An `AnyOf` constraint cannot be nested inside, as it cannot be represented textually.
+	All(Vec<VersionConstraint>),
+	/// At least one of the constraints must match.
+	AnyOf(Vec<VersionConstraint>),
 }
 
 impl Serialize for VersionConstraint {
@@ -136,36 +481,298 @@ impl<'de> Deserialize<'de> for VersionConstraint {
 	}
 }
 
+/// Returns the ranges of the parts of `input[start..end]` separated by `sep`.
+fn split_ranges<'s>(
+	input: &'s str,
+	start: usize,
+	end: usize,
+	sep: &'s str,
+) -> impl Iterator<Item = (usize, usize)> + 's {
+	let mut part_start = start;
+	input[start..end]
+		.match_indices(sep)
+		.map(move |(i, _)| start + i)
+		.chain([end])
+		.map(move |part_end| {
+			let range = (part_start, part_end);
+			part_start = part_end + sep.len();
+			range
+		})
+}
+
+/// Function creating a constraint from a version.
+type Constructor = fn(Version) -> VersionConstraint;
+
+/// Parses the constraint without `,` nor `||` in `input[start..end]`.
+fn parse_simple_constraint(
+	input: &str,
+	start: usize,
+	end: usize,
+) -> Result<VersionConstraint, ParseError> {
+	use VersionConstraint as C;
+	let cursor = Cursor::new(input, start, end);
+	let rest = cursor.rest();
+	if rest == "*" {
+		return Ok(C::Any);
+	}
+	if rest.is_empty() {
+		return Err(cursor.err("a version constraint"));
+	}
+	let ops: [(&str, Constructor); 8] = [
+		("<=", C::LessOrEqual),
+		(">=", C::GreaterOrEqual),
+		("=", C::Equal),
+		("<", C::Less),
+		(">", C::Greater),
+		("~", C::Tilde),
+		("^", C::Caret),
+		("", C::Equal),
+	];
+	let (op, f) = ops
+		.into_iter()
+		.find(|(op, _)| rest.starts_with(op))
+		.unwrap();
+	let version = Version::parse(input, cursor.pos + op.len(), cursor.end)?;
+	Ok(f(version))
+}
+
 impl TryFrom<&str> for VersionConstraint {
-	type Error = ParseIntError;
+	type Error = ParseError;
 
 	fn try_from(value: &str) -> Result<Self, Self::Error> {
-		match value.trim() {
-			"*" => Ok(Self::Any),
-			s if s.starts_with('=') => Ok(Self::Equal(Version::try_from(&s[1..])?)),
-			s if s.starts_with("<=") => Ok(Self::LessOrEqual(Version::try_from(&s[2..])?)),
-			s if s.starts_with('<') => Ok(Self::Less(Version::try_from(&s[1..])?)),
-			s if s.starts_with(">=") => Ok(Self::GreaterOrEqual(Version::try_from(&s[2..])?)),
-			s if s.starts_with('>') => Ok(Self::Greater(Version::try_from(&s[1..])?)),
-			_ => Ok(Self::Equal(Version::try_from(value)?)),
+		let mut alternatives = split_ranges(value, 0, value.len(), "||")
+			.map(|(start, end)| {
+				let mut constraints = split_ranges(value, start, end, ",")
+					.map(|(start, end)| parse_simple_constraint(value, start, end))
+					.collect::<Result<Vec<_>, _>>()?;
+				if constraints.len() == 1 {
+					Ok(constraints.remove(0))
+				} else {
+					Ok(Self::All(constraints))
+				}
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+		if alternatives.len() == 1 {
+			Ok(alternatives.remove(0))
+		} else {
+			Ok(Self::AnyOf(alternatives))
+		}
+	}
+}
+
+/// A bound of an [`Interval`].
+#[derive(Clone)]
+struct Bound {
+	/// The version at the bound.
+	version: Version,
+	/// Tells whether the version is included in the interval.
+	inclusive: bool,
+}
+
+/// An interval of versions. A missing bound means the interval is unbounded on this side.
+#[derive(Clone, Default)]
+struct Interval {
+	lower: Option<Bound>,
+	upper: Option<Bound>,
+}
+
+impl Interval {
+	/// Returns the interval from `version` (included) to `upper` (excluded).
+	fn range(version: &Version, upper: Version) -> Self {
+		Self {
+			lower: Some(Bound {
+				version: version.clone(),
+				inclusive: true,
+			}),
+			upper: Some(Bound {
+				version: upper,
+				inclusive: false,
+			}),
+		}
+	}
+
+	/// Returns the intersection of both intervals. If empty, the function returns `None`.
+	fn intersect(&self, other: &Self) -> Option<Self> {
+		// Keep the tightest bound on each side
+		let lower = match (&self.lower, &other.lower) {
+			(Some(a), Some(b)) => match a.version.cmp(&b.version) {
+				Ordering::Less => Some(b.clone()),
+				Ordering::Greater => Some(a.clone()),
+				Ordering::Equal => Some(Bound {
+					version: a.version.clone(),
+					inclusive: a.inclusive && b.inclusive,
+				}),
+			},
+			(a, b) => a.clone().or(b.clone()),
+		};
+		let upper = match (&self.upper, &other.upper) {
+			(Some(a), Some(b)) => match a.version.cmp(&b.version) {
+				Ordering::Less => Some(a.clone()),
+				Ordering::Greater => Some(b.clone()),
+				Ordering::Equal => Some(Bound {
+					version: a.version.clone(),
+					inclusive: a.inclusive && b.inclusive,
+				}),
+			},
+			(a, b) => a.clone().or(b.clone()),
+		};
+		if let (Some(l), Some(u)) = (&lower, &upper) {
+			match l.version.cmp(&u.version) {
+				Ordering::Greater => return None,
+				Ordering::Equal if !(l.inclusive && u.inclusive) => return None,
+				_ => {}
+			}
+		}
+		Some(Self {
+			lower,
+			upper,
+		})
+	}
+
+	/// Returns the constraint equivalent to the interval.
+	fn to_constraint(&self) -> VersionConstraint {
+		use VersionConstraint as C;
+		let lower = self.lower.as_ref().map(|b| match b.inclusive {
+			true => C::GreaterOrEqual(b.version.clone()),
+			false => C::Greater(b.version.clone()),
+		});
+		let upper = self.upper.as_ref().map(|b| match b.inclusive {
+			true => C::LessOrEqual(b.version.clone()),
+			false => C::Less(b.version.clone()),
+		});
+		match (lower, upper) {
+			(None, None) => C::Any,
+			(Some(c), None) | (None, Some(c)) => c,
+			(Some(C::GreaterOrEqual(l)), Some(C::LessOrEqual(u))) if l.cmp(&u).is_eq() => {
+				C::Equal(l)
+			}
+			(Some(l), Some(u)) => C::All(vec![l, u]),
 		}
 	}
 }
 
 impl VersionConstraint {
+	/// Returns the list of intervals of versions matching the constraint.
+	fn intervals(&self) -> Vec<Interval> {
+		let bound = |version: &Version, inclusive| {
+			Some(Bound {
+				version: version.clone(),
+				inclusive,
+			})
+		};
+		match self {
+			Self::Any => vec![Interval::default()],
+			Self::Equal(v) => vec![Interval {
+				lower: bound(v, true),
+				upper: bound(v, true),
+			}],
+			Self::LessOrEqual(v) => vec![Interval {
+				lower: None,
+				upper: bound(v, true),
+			}],
+			Self::Less(v) => vec![Interval {
+				lower: None,
+				upper: bound(v, false),
+			}],
+			Self::GreaterOrEqual(v) => vec![Interval {
+				lower: bound(v, true),
+				upper: None,
+			}],
+			Self::Greater(v) => vec![Interval {
+				lower: bound(v, false),
+				upper: None,
+			}],
+			Self::Tilde(v) => {
+				let idx = v.components.len().min(2) - 1;
+				vec![Interval::range(v, v.bump(idx))]
+			}
+			Self::Caret(v) => {
+				let idx = v
+					.components
+					.iter()
+					.position(|c| component_value(c) != 0)
+					.unwrap_or(v.components.len() - 1);
+				vec![Interval::range(v, v.bump(idx))]
+			}
+			Self::All(constraints) => {
+				constraints
+					.iter()
+					.fold(vec![Interval::default()], |intervals, c| {
+						let other = c.intervals();
+						intervals
+							.iter()
+							.flat_map(|a| other.iter().filter_map(|b| a.intersect(b)))
+							.collect()
+					})
+			}
+			Self::AnyOf(constraints) => constraints.iter().flat_map(Self::intervals).collect(),
+		}
+	}
+
 	/// Tells whether the given version matches the constraint.
 	pub fn is_valid(&self, version: &Version) -> bool {
 		match self {
 			Self::Any => true,
 			Self::Equal(v) => matches!(version.cmp(v), Ordering::Equal),
 			Self::LessOrEqual(v) => matches!(version.cmp(v), Ordering::Less | Ordering::Equal),
-			Self::Less(v) => matches!(version.cmp(v), Ordering::Less),
+			// Pre-releases of an excluded upper bound are excluded as well, so that `<2.0` does
+			// not match `2.0-rc1`
+			Self::Less(v) => {
+				matches!(version.cmp(v), Ordering::Less) && !version.is_pre_release_of(v)
+			}
 			Self::GreaterOrEqual(v) => {
 				matches!(version.cmp(v), Ordering::Greater | Ordering::Equal)
 			}
 			Self::Greater(v) => matches!(version.cmp(v), Ordering::Greater),
+			Self::Tilde(_) | Self::Caret(_) => self
+				.intervals()
+				.iter()
+				.any(|i| i.to_constraint().is_valid(version)),
+			Self::All(constraints) => constraints.iter().all(|c| c.is_valid(version)),
+			Self::AnyOf(constraints) => constraints.iter().any(|c| c.is_valid(version)),
+		}
+	}
+
+	/// Returns the normalized form of the constraint, in which shorthands are expanded and each
+	/// alternative is reduced to at most one lower bound and one upper bound.
+	///
+	/// If no version can match the constraint, the function returns `None`.
+	pub fn normalize(&self) -> Option<Self> {
+		let mut alternatives: Vec<_> = self
+			.intervals()
+			.iter()
+			.map(Interval::to_constraint)
+			.collect();
+		alternatives.dedup();
+		match alternatives.len() {
+			0 => None,
+			1 => alternatives.pop(),
+			_ => Some(Self::AnyOf(alternatives)),
 		}
 	}
+
+	/// Returns the normalized constraint matching the versions that match both `self` and
+	/// `other`.
+	///
+	/// If no version can match both constraints, the function returns `None`.
+	pub fn intersect(&self, other: &Self) -> Option<Self> {
+		Self::All(vec![self.clone(), other.clone()]).normalize()
+	}
+}
+
+/// Writes the given constraints, separated by `sep`.
+fn write_list(
+	f: &mut fmt::Formatter<'_>,
+	constraints: &[VersionConstraint],
+	sep: &str,
+) -> fmt::Result {
+	for (i, c) in constraints.iter().enumerate() {
+		if i > 0 {
+			write!(f, "{sep}")?;
+		}
+		write!(f, "{c}")?;
+	}
+	Ok(())
 }
 
 impl fmt::Display for VersionConstraint {
@@ -177,6 +784,173 @@ impl fmt::Display for VersionConstraint {
 			Self::Less(v) => write!(f, "<{v}"),
 			Self::GreaterOrEqual(v) => write!(f, ">={v}"),
 			Self::Greater(v) => write!(f, ">{v}"),
+			Self::Tilde(v) => write!(f, "~{v}"),
+			Self::Caret(v) => write!(f, "^{v}"),
+			Self::All(constraints) => write_list(f, constraints, ", "),
+			Self::AnyOf(constraints) => write_list(f, constraints, " || "),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn v(s: &str) -> Version {
+		Version::try_from(s).unwrap()
+	}
+
+	#[test]
+	fn parse_invalid() {
+		for s in ["", "1..2", "1.2-", "a:1.0", "1.2_3", "1.0-rc!"] {
+			assert!(Version::try_from(s).is_err(), "{s}");
+		}
+	}
+
+	#[test]
+	fn parse_error_position() {
+		let cases = [
+			("1.x!", 3, "`.`, `-` or end of input"),
+			("1..2", 2, "a digit or a letter"),
+			("a:1.0", 0, "an epoch"),
+			("1.0-rc1-rc2", 8, "a revision number"),
+			("1.0-99999999999", 4, "a number fitting in 32 bits"),
+		];
+		for (input, position, expected) in cases {
+			let err = Version::try_from(input).err().unwrap();
+			assert_eq!(err.position, position, "{input}");
+			assert_eq!(err.expected, expected, "{input}");
+		}
+		let err = VersionConstraint::try_from(">=1.0, <2.x!").err().unwrap();
+		assert_eq!(err.input, ">=1.0, <2.x!");
+		assert_eq!(err.position, 11);
+		let err = VersionConstraint::try_from(">=1.0,").err().unwrap();
+		assert_eq!(err.expected, "a version constraint");
+	}
+
+	#[test]
+	fn display_round_trip() {
+		for s in [
+			"1",
+			"1.2.3",
+			"2024a",
+			"1.1.1w",
+			"1.2.3-rc1",
+			"1.0-2",
+			"2:1.0-beta.2-3",
+		] {
+			assert_eq!(v(s).to_string(), s);
 		}
 	}
+
+	#[test]
+	fn ordering() {
+		let sorted = [
+			"1.0",
+			"1.0.1",
+			"1.2",
+			"1.10",
+			"2.0-alpha",
+			"2.0-rc1",
+			"2.0-rc2",
+			"2.0",
+			"2.0-1",
+			"2.0-2",
+			"2024",
+			"2024a",
+			"2024b",
+			"1:0.1",
+		];
+		for w in sorted.windows(2) {
+			assert!(v(w[0]) < v(w[1]), "{} < {}", w[0], w[1]);
+		}
+		assert_eq!(v("1.0").cmp(&v("1.0.0")), Ordering::Equal);
+		assert_eq!(v("1.01").cmp(&v("1.1")), Ordering::Equal);
+	}
+
+	#[test]
+	fn equality() {
+		use std::collections::HashSet;
+
+		let equal = [
+			("1.0", "1.0.0"),
+			("1.01", "1.1"),
+			("2.0", "2.0-0"),
+			("1.0-rc1", "1.0-rc01.0"),
+			("0:1.2", "1.2"),
+		];
+		for (a, b) in equal {
+			assert!(v(a) == v(b), "{a} == {b}");
+			let set = HashSet::from([v(a), v(b)]);
+			assert_eq!(set.len(), 1, "{a} == {b}");
+		}
+		for (a, b) in [("1.0", "1.0-rc1"), ("1.0a", "1.0"), ("1.0", "1:1.0")] {
+			assert!(v(a) != v(b), "{a} != {b}");
+		}
+	}
+
+	fn c(s: &str) -> VersionConstraint {
+		VersionConstraint::try_from(s).unwrap()
+	}
+
+	#[test]
+	fn constraint_round_trip() {
+		for s in [
+			"*",
+			"=1.0",
+			">=1.2, <2.0",
+			"<1 || >=2, <3 || =4",
+			"~1.2",
+			"^0.2.3",
+		] {
+			assert_eq!(c(s).to_string(), s);
+		}
+	}
+
+	#[test]
+	fn constraint_is_valid() {
+		let cases = [
+			(">=1.2, <2.0", "1.2", true),
+			(">=1.2, <2.0", "1.9.9", true),
+			(">=1.2, <2.0", "2.0", false),
+			("<1 || >=2", "1.5", false),
+			("<1 || >=2", "2.1", true),
+			("~1.2.3", "1.2.9", true),
+			("~1.2.3", "1.3", false),
+			("~1", "1.9", true),
+			("^1.2.3", "1.9", true),
+			("^1.2.3", "2.0", false),
+			("^0.2.3", "0.2.9", true),
+			("^0.2.3", "0.3", false),
+			("^0.0.3", "0.0.4", false),
+			("<2.0", "2.0-rc1", false),
+			("<2.0", "1.9-rc1", true),
+			("<2.0-rc2", "2.0-rc1", true),
+			("<=2.0", "2.0-rc1", true),
+			("^1.2", "2.0-rc1", false),
+			("~1.2", "1.3-rc1", false),
+			(">=1.2, <2.0", "1.2-rc1", false),
+		];
+		for (constraint, version, valid) in cases {
+			assert_eq!(
+				c(constraint).is_valid(&v(version)),
+				valid,
+				"{constraint} {version}"
+			);
+		}
+	}
+
+	#[test]
+	fn constraint_normalize() {
+		assert_eq!(c("^1.2").normalize().unwrap().to_string(), ">=1.2, <2");
+		assert_eq!(
+			c(">=1.0, >1.2, <=3, <2").normalize().unwrap().to_string(),
+			">1.2, <2"
+		);
+		assert_eq!(c(">=1.0, <=1.0").normalize().unwrap().to_string(), "=1.0");
+		assert!(c(">=2, <1").normalize().is_none());
+		let i = c(">=1.0 || <0.5").intersect(&c("<1.5")).unwrap();
+		assert_eq!(i.to_string(), ">=1.0, <1.5 || <0.5");
+		assert!(c("=1.0").intersect(&c("=2.0")).is_none());
+	}
 }